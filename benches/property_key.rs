@@ -0,0 +1,38 @@
+//! Compare property access using string slices against PropertyKey
+//!
+//! Run with `cargo bench --bench property_key`.
+
+use std::time::Instant;
+
+extern crate mujs;
+
+const ITERATIONS: u32 = 1_000_000;
+
+fn measure<F>(name: &str, func: F) where F: Fn() {
+    let start = Instant::now();
+    func();
+    let elapsed = start.elapsed();
+    let ns = elapsed.as_secs() * 1_000_000_000 + elapsed.subsec_nanos() as u64;
+    println!("{:<24} {:>8} ns/iter", name, ns / ITERATIONS as u64);
+}
+
+pub fn main() {
+    let state = mujs::State::new(mujs::JS_STRICT);
+    state.dostring("var point = {x: 1.0, y: 2.0};").unwrap();
+    state.getglobal("point").unwrap();
+
+    measure("getproperty(&str)", || {
+        for _ in 0..ITERATIONS {
+            state.getproperty(0, "x").unwrap();
+            state.pop(1);
+        }
+    });
+
+    let x = mujs::PropertyKey::new("x").unwrap();
+    measure("getproperty(PropertyKey)", || {
+        for _ in 0..ITERATIONS {
+            state.getproperty(0, &x).unwrap();
+            state.pop(1);
+        }
+    });
+}
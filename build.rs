@@ -1,5 +0,0 @@
-extern crate gcc;
-
-fn main() {
-    gcc::compile_library("libmujs.a", &["mujs/one.c"]);
-}
@@ -0,0 +1,116 @@
+extern crate cc;
+#[cfg(feature = "system")]
+extern crate pkg_config;
+
+use std::fs;
+use std::path::Path;
+
+/// Releases introducing API gated with `mujs_<major>_<minor>` cfgs
+static GATED_VERSIONS: &[(u32, u32)] = &[(1, 1), (1, 2)];
+
+/// Version assumed when it can not be detected, the first release
+/// covering the ungated API
+static BASE_VERSION: (u32, u32, u32) = (1, 0, 0);
+
+/// Parse a `major.minor[.patch]` version string
+#[cfg(feature = "system")]
+fn parse_version(version: &str) -> Option<(u32, u32, u32)> {
+    let mut parts = version.trim().split('.').map(|part| part.parse::<u32>());
+    let major = parts.next()?.ok()?;
+    let minor = parts.next()?.ok()?;
+    let patch = match parts.next() {
+        Some(patch) => patch.ok()?,
+        None => 0
+    };
+    Some((major, minor, patch))
+}
+
+/// Read the version from the `JS_VERSION_*` defines of mujs.h,
+/// which are missing before 1.1
+fn header_version(include: &Path) -> Option<(u32, u32, u32)> {
+    let header = fs::read_to_string(include.join("mujs.h")).ok()?;
+    let define = |name: &str| -> Option<u32> {
+        header.lines()
+            .filter_map(|line| {
+                let mut words = line.split_whitespace();
+                match (words.next(), words.next(), words.next()) {
+                    (Some("#define"), Some(word), Some(value)) if word == name => value.parse().ok(),
+                    _ => None
+                }
+            })
+            .next()
+    };
+    Some((define("JS_VERSION_MAJOR")?, define("JS_VERSION_MINOR")?,
+          define("JS_VERSION_PATCH").unwrap_or(0)))
+}
+
+#[cfg(feature = "system")]
+fn probe_system() -> Option<(u32, u32, u32)> {
+    match pkg_config::Config::new().probe("mujs") {
+        Ok(library) => {
+            let version = library.include_paths.iter()
+                .filter_map(|include| header_version(include))
+                .next()
+                .or_else(|| parse_version(&library.version))
+                .unwrap_or(BASE_VERSION);
+            Some(version)
+        },
+        Err(err) => {
+            if cfg!(not(feature = "vendored")) {
+                panic!("Failed to find libmujs using pkg-config: {}", err);
+            }
+            println!("cargo:warning=libmujs not found using pkg-config, building vendored sources");
+            None
+        }
+    }
+}
+
+#[cfg(not(feature = "system"))]
+fn probe_system() -> Option<(u32, u32, u32)> {
+    None
+}
+
+#[cfg(feature = "vendored")]
+fn build_vendored() -> (u32, u32, u32) {
+    let source = Path::new(&std::env::var("CARGO_MANIFEST_DIR").unwrap()).join("mujs");
+    if !source.join("one.c").exists() {
+        panic!("MuJS sources not found in {}, run `git submodule update --init` \
+                or enable the `system` feature", source.display());
+    }
+    println!("cargo:rerun-if-changed={}", source.display());
+
+    cc::Build::new()
+        .file(source.join("one.c"))
+        .include(&source)
+        .warnings(false)
+        .compile("mujs");
+
+    if cfg!(not(target_env = "msvc")) {
+        println!("cargo:rustc-link-lib=m");
+    }
+    println!("cargo:include={}", source.display());
+    header_version(&source).unwrap_or(BASE_VERSION)
+}
+
+#[cfg(not(feature = "vendored"))]
+fn build_vendored() -> (u32, u32, u32) {
+    panic!("Either the `vendored` or the `system` feature must be enabled");
+}
+
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+
+    let (major, minor, patch) = match probe_system() {
+        Some(version) => version,
+        None => build_vendored()
+    };
+
+    println!("cargo:version={}.{}.{}", major, minor, patch);
+    println!("cargo:rustc-env=MUJS_VERSION={}.{}.{}", major, minor, patch);
+    for &(gated_major, gated_minor) in GATED_VERSIONS {
+        println!("cargo:rustc-check-cfg=cfg(mujs_{}_{})", gated_major, gated_minor);
+        if (major, minor) >= (gated_major, gated_minor) {
+            println!("cargo:rustc-cfg=mujs_{}_{}", gated_major, gated_minor);
+        }
+    }
+}
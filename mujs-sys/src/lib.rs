@@ -0,0 +1,263 @@
+//! # Raw MuJS bindings
+//!
+//! Declarations of the complete C API in `mujs.h` together with the
+//! build of the MuJS library. These are unsafe low level bindings,
+//! see the [mujs](https://crates.io/crates/mujs) crate for a safe
+//! interface.
+//!
+//! For documentation of the functions see [MuJS Reference
+//! Manual](http://dev.mujs.com/docs/reference.html).
+//!
+//! ## Linking
+//!
+//! With the default `vendored` feature the MuJS sources in the
+//! `mujs` directory are built and linked statically. The `system`
+//! feature links against an installed libmujs found using
+//! pkg-config instead, falling back to the vendored sources when
+//! both are enabled.
+//!
+//! The version of the library is detected at build time. Functions
+//! added after MuJS 1.0 are only declared when present, gated by
+//! the `mujs_1_1` and `mujs_1_2` cfgs.
+
+#![allow(non_camel_case_types)]
+#![allow(non_snake_case)]
+
+extern crate libc;
+
+use libc::{c_char, c_double, c_int, c_short, c_uint, c_ushort, c_void};
+
+/// Opaque interpreter state
+#[repr(C)]
+pub struct js_State {
+    _private: [u8; 0],
+}
+
+pub type js_Alloc = Option<unsafe extern "C" fn(memctx: *mut c_void, ptr: *mut c_void,
+                                                size: c_int) -> *mut c_void>;
+pub type js_Panic = Option<unsafe extern "C" fn(J: *mut js_State)>;
+pub type js_CFunction = Option<unsafe extern "C" fn(J: *mut js_State)>;
+pub type js_Finalize = Option<unsafe extern "C" fn(J: *mut js_State, p: *mut c_void)>;
+pub type js_HasProperty = Option<unsafe extern "C" fn(J: *mut js_State, p: *mut c_void,
+                                                      name: *const c_char) -> c_int>;
+pub type js_Put = Option<unsafe extern "C" fn(J: *mut js_State, p: *mut c_void,
+                                              name: *const c_char) -> c_int>;
+pub type js_Delete = Option<unsafe extern "C" fn(J: *mut js_State, p: *mut c_void,
+                                                 name: *const c_char) -> c_int>;
+pub type js_Report = Option<unsafe extern "C" fn(J: *mut js_State, message: *const c_char)>;
+
+/// Version of the linked MuJS library as `major.minor.patch`
+pub const MUJS_VERSION: &str = env!("MUJS_VERSION");
+
+/// State constructor flags
+pub const JS_STRICT: c_int = 1;
+
+/// RegExp flags
+pub const JS_REGEXP_G: c_int = 1;
+pub const JS_REGEXP_I: c_int = 2;
+pub const JS_REGEXP_M: c_int = 4;
+
+/// Property attribute flags
+pub const JS_READONLY: c_int = 1;
+pub const JS_DONTENUM: c_int = 2;
+pub const JS_DONTCONF: c_int = 4;
+
+/// Values returned by js_type()
+#[cfg(mujs_1_1)]
+pub const JS_ISUNDEFINED: c_int = 0;
+#[cfg(mujs_1_1)]
+pub const JS_ISNULL: c_int = 1;
+#[cfg(mujs_1_1)]
+pub const JS_ISBOOLEAN: c_int = 2;
+#[cfg(mujs_1_1)]
+pub const JS_ISNUMBER: c_int = 3;
+#[cfg(mujs_1_1)]
+pub const JS_ISSTRING: c_int = 4;
+#[cfg(mujs_1_1)]
+pub const JS_ISFUNCTION: c_int = 5;
+#[cfg(mujs_1_1)]
+pub const JS_ISOBJECT: c_int = 6;
+
+extern "C" {
+    pub fn js_newstate(alloc: js_Alloc, actx: *mut c_void, flags: c_int) -> *mut js_State;
+    pub fn js_setcontext(J: *mut js_State, uctx: *mut c_void);
+    pub fn js_getcontext(J: *mut js_State) -> *mut c_void;
+    pub fn js_setreport(J: *mut js_State, report: js_Report);
+    pub fn js_atpanic(J: *mut js_State, panic: js_Panic) -> js_Panic;
+    pub fn js_freestate(J: *mut js_State);
+    pub fn js_gc(J: *mut js_State, report: c_int);
+
+    pub fn js_dostring(J: *mut js_State, source: *const c_char) -> c_int;
+    pub fn js_dofile(J: *mut js_State, filename: *const c_char) -> c_int;
+    pub fn js_ploadstring(J: *mut js_State, filename: *const c_char, source: *const c_char) -> c_int;
+    pub fn js_ploadfile(J: *mut js_State, filename: *const c_char) -> c_int;
+    pub fn js_pcall(J: *mut js_State, n: c_int) -> c_int;
+    pub fn js_pconstruct(J: *mut js_State, n: c_int) -> c_int;
+
+    /// Returns a pointer to a `jmp_buf` to be passed to `setjmp`,
+    /// which can not be done from Rust
+    pub fn js_savetry(J: *mut js_State) -> *mut c_void;
+    pub fn js_endtry(J: *mut js_State);
+
+    pub fn js_report(J: *mut js_State, message: *const c_char);
+
+    pub fn js_newerror(J: *mut js_State, message: *const c_char);
+    pub fn js_newevalerror(J: *mut js_State, message: *const c_char);
+    pub fn js_newrangeerror(J: *mut js_State, message: *const c_char);
+    pub fn js_newreferenceerror(J: *mut js_State, message: *const c_char);
+    pub fn js_newsyntaxerror(J: *mut js_State, message: *const c_char);
+    pub fn js_newtypeerror(J: *mut js_State, message: *const c_char);
+    pub fn js_newurierror(J: *mut js_State, message: *const c_char);
+
+    pub fn js_error(J: *mut js_State, fmt: *const c_char, ...) -> !;
+    pub fn js_evalerror(J: *mut js_State, fmt: *const c_char, ...) -> !;
+    pub fn js_rangeerror(J: *mut js_State, fmt: *const c_char, ...) -> !;
+    pub fn js_referenceerror(J: *mut js_State, fmt: *const c_char, ...) -> !;
+    pub fn js_syntaxerror(J: *mut js_State, fmt: *const c_char, ...) -> !;
+    pub fn js_typeerror(J: *mut js_State, fmt: *const c_char, ...) -> !;
+    pub fn js_urierror(J: *mut js_State, fmt: *const c_char, ...) -> !;
+    pub fn js_throw(J: *mut js_State) -> !;
+
+    pub fn js_loadstring(J: *mut js_State, filename: *const c_char, source: *const c_char);
+    pub fn js_loadfile(J: *mut js_State, filename: *const c_char);
+
+    pub fn js_eval(J: *mut js_State);
+    pub fn js_call(J: *mut js_State, n: c_int);
+    pub fn js_construct(J: *mut js_State, n: c_int);
+
+    pub fn js_ref(J: *mut js_State) -> *const c_char;
+    pub fn js_unref(J: *mut js_State, reference: *const c_char);
+
+    pub fn js_getregistry(J: *mut js_State, name: *const c_char);
+    pub fn js_setregistry(J: *mut js_State, name: *const c_char);
+    pub fn js_delregistry(J: *mut js_State, name: *const c_char);
+
+    pub fn js_getglobal(J: *mut js_State, name: *const c_char);
+    pub fn js_setglobal(J: *mut js_State, name: *const c_char);
+    pub fn js_defglobal(J: *mut js_State, name: *const c_char, atts: c_int);
+    #[cfg(mujs_1_1)]
+    pub fn js_delglobal(J: *mut js_State, name: *const c_char);
+
+    pub fn js_hasproperty(J: *mut js_State, idx: c_int, name: *const c_char) -> c_int;
+    pub fn js_getproperty(J: *mut js_State, idx: c_int, name: *const c_char);
+    pub fn js_setproperty(J: *mut js_State, idx: c_int, name: *const c_char);
+    pub fn js_defproperty(J: *mut js_State, idx: c_int, name: *const c_char, atts: c_int);
+    pub fn js_delproperty(J: *mut js_State, idx: c_int, name: *const c_char);
+    pub fn js_defaccessor(J: *mut js_State, idx: c_int, name: *const c_char, atts: c_int);
+
+    pub fn js_getlength(J: *mut js_State, idx: c_int) -> c_int;
+    pub fn js_setlength(J: *mut js_State, idx: c_int, len: c_int);
+    pub fn js_hasindex(J: *mut js_State, idx: c_int, i: c_int) -> c_int;
+    pub fn js_getindex(J: *mut js_State, idx: c_int, i: c_int);
+    pub fn js_setindex(J: *mut js_State, idx: c_int, i: c_int);
+    pub fn js_delindex(J: *mut js_State, idx: c_int, i: c_int);
+
+    pub fn js_currentfunction(J: *mut js_State);
+    #[cfg(mujs_1_1)]
+    pub fn js_currentfunctiondata(J: *mut js_State) -> *mut c_void;
+    pub fn js_pushglobal(J: *mut js_State);
+    pub fn js_pushundefined(J: *mut js_State);
+    pub fn js_pushnull(J: *mut js_State);
+    pub fn js_pushboolean(J: *mut js_State, v: c_int);
+    pub fn js_pushnumber(J: *mut js_State, v: c_double);
+    pub fn js_pushstring(J: *mut js_State, v: *const c_char);
+    pub fn js_pushlstring(J: *mut js_State, v: *const c_char, n: c_int);
+    pub fn js_pushliteral(J: *mut js_State, v: *const c_char);
+
+    #[cfg(mujs_1_2)]
+    pub fn js_newobjectx(J: *mut js_State);
+    pub fn js_newobject(J: *mut js_State);
+    pub fn js_newarray(J: *mut js_State);
+    pub fn js_newboolean(J: *mut js_State, v: c_int);
+    pub fn js_newnumber(J: *mut js_State, v: c_double);
+    pub fn js_newstring(J: *mut js_State, v: *const c_char);
+    pub fn js_newcfunction(J: *mut js_State, fun: js_CFunction, name: *const c_char, length: c_int);
+    #[cfg(mujs_1_1)]
+    pub fn js_newcfunctionx(J: *mut js_State, fun: js_CFunction, name: *const c_char, length: c_int,
+                            data: *mut c_void, finalize: js_Finalize);
+    pub fn js_newcconstructor(J: *mut js_State, fun: js_CFunction, con: js_CFunction,
+                              name: *const c_char, length: c_int);
+    pub fn js_newuserdata(J: *mut js_State, tag: *const c_char, data: *mut c_void,
+                          finalize: js_Finalize);
+    pub fn js_newuserdatax(J: *mut js_State, tag: *const c_char, data: *mut c_void,
+                           has: js_HasProperty, put: js_Put, del: js_Delete,
+                           finalize: js_Finalize);
+    pub fn js_newregexp(J: *mut js_State, pattern: *const c_char, flags: c_int);
+
+    pub fn js_pushiterator(J: *mut js_State, idx: c_int, own: c_int);
+    pub fn js_nextiterator(J: *mut js_State, idx: c_int) -> *const c_char;
+
+    pub fn js_isdefined(J: *mut js_State, idx: c_int) -> c_int;
+    pub fn js_isundefined(J: *mut js_State, idx: c_int) -> c_int;
+    pub fn js_isnull(J: *mut js_State, idx: c_int) -> c_int;
+    pub fn js_isboolean(J: *mut js_State, idx: c_int) -> c_int;
+    pub fn js_isnumber(J: *mut js_State, idx: c_int) -> c_int;
+    pub fn js_isstring(J: *mut js_State, idx: c_int) -> c_int;
+    pub fn js_isprimitive(J: *mut js_State, idx: c_int) -> c_int;
+    pub fn js_isobject(J: *mut js_State, idx: c_int) -> c_int;
+    pub fn js_isarray(J: *mut js_State, idx: c_int) -> c_int;
+    pub fn js_isregexp(J: *mut js_State, idx: c_int) -> c_int;
+    pub fn js_iscoercible(J: *mut js_State, idx: c_int) -> c_int;
+    pub fn js_iscallable(J: *mut js_State, idx: c_int) -> c_int;
+    pub fn js_isuserdata(J: *mut js_State, idx: c_int, tag: *const c_char) -> c_int;
+    #[cfg(mujs_1_1)]
+    pub fn js_iserror(J: *mut js_State, idx: c_int) -> c_int;
+    #[cfg(mujs_1_2)]
+    pub fn js_isnumberobject(J: *mut js_State, idx: c_int) -> c_int;
+    #[cfg(mujs_1_2)]
+    pub fn js_isstringobject(J: *mut js_State, idx: c_int) -> c_int;
+    #[cfg(mujs_1_2)]
+    pub fn js_isbooleanobject(J: *mut js_State, idx: c_int) -> c_int;
+
+    pub fn js_toboolean(J: *mut js_State, idx: c_int) -> c_int;
+    pub fn js_tonumber(J: *mut js_State, idx: c_int) -> c_double;
+    pub fn js_tostring(J: *mut js_State, idx: c_int) -> *const c_char;
+    pub fn js_touserdata(J: *mut js_State, idx: c_int, tag: *const c_char) -> *mut c_void;
+
+    #[cfg(mujs_1_1)]
+    pub fn js_trystring(J: *mut js_State, idx: c_int, error: *const c_char) -> *const c_char;
+    #[cfg(mujs_1_1)]
+    pub fn js_trynumber(J: *mut js_State, idx: c_int, error: c_double) -> c_double;
+    #[cfg(mujs_1_1)]
+    pub fn js_tryinteger(J: *mut js_State, idx: c_int, error: c_int) -> c_int;
+    #[cfg(mujs_1_1)]
+    pub fn js_tryboolean(J: *mut js_State, idx: c_int, error: c_int) -> c_int;
+
+    pub fn js_tointeger(J: *mut js_State, idx: c_int) -> c_int;
+    pub fn js_toint32(J: *mut js_State, idx: c_int) -> c_int;
+    pub fn js_touint32(J: *mut js_State, idx: c_int) -> c_uint;
+    pub fn js_toint16(J: *mut js_State, idx: c_int) -> c_short;
+    pub fn js_touint16(J: *mut js_State, idx: c_int) -> c_ushort;
+
+    pub fn js_gettop(J: *mut js_State) -> c_int;
+    pub fn js_pop(J: *mut js_State, n: c_int);
+    pub fn js_rot(J: *mut js_State, n: c_int);
+    pub fn js_copy(J: *mut js_State, idx: c_int);
+    pub fn js_remove(J: *mut js_State, idx: c_int);
+    pub fn js_insert(J: *mut js_State, idx: c_int);
+    pub fn js_replace(J: *mut js_State, idx: c_int);
+
+    pub fn js_dup(J: *mut js_State);
+    pub fn js_dup2(J: *mut js_State);
+    pub fn js_rot2(J: *mut js_State);
+    pub fn js_rot3(J: *mut js_State);
+    pub fn js_rot4(J: *mut js_State);
+    pub fn js_rot2pop1(J: *mut js_State);
+    pub fn js_rot3pop2(J: *mut js_State);
+
+    pub fn js_concat(J: *mut js_State);
+    pub fn js_compare(J: *mut js_State, okay: *mut c_int) -> c_int;
+    pub fn js_equal(J: *mut js_State) -> c_int;
+    pub fn js_strictequal(J: *mut js_State) -> c_int;
+    pub fn js_instanceof(J: *mut js_State) -> c_int;
+    pub fn js_typeof(J: *mut js_State, idx: c_int) -> *const c_char;
+    #[cfg(mujs_1_1)]
+    pub fn js_type(J: *mut js_State, idx: c_int) -> c_int;
+
+    #[cfg(mujs_1_2)]
+    pub fn js_repr(J: *mut js_State, idx: c_int);
+    #[cfg(mujs_1_2)]
+    pub fn js_torepr(J: *mut js_State, idx: c_int) -> *const c_char;
+    #[cfg(mujs_1_2)]
+    pub fn js_tryrepr(J: *mut js_State, idx: c_int, error: *const c_char) -> *const c_char;
+}
@@ -0,0 +1,166 @@
+//! Typed access to the arguments of host functions
+
+use std::fmt;
+
+use {State, FromJs, ToJs};
+
+/// Error thrown into the script by a function registered with
+/// State::new_function()
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CallError {
+    /// Thrown as a TypeError, returned by Args on type mismatch
+    Type(String),
+    /// Thrown as an Error
+    Error(String),
+}
+
+impl fmt::Display for CallError {
+    fn fmt(self: &CallError, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CallError::Type(ref message) => write!(f, "TypeError: {}", message),
+            CallError::Error(ref message) => write!(f, "Error: {}", message)
+        }
+    }
+}
+
+impl From<String> for CallError {
+    fn from(message: String) -> CallError {
+        CallError::Error(message)
+    }
+}
+
+impl<'a> From<&'a str> for CallError {
+    fn from(message: &'a str) -> CallError {
+        CallError::Error(message.to_string())
+    }
+}
+
+/// Arguments of a call to a function registered with
+/// State::new_function()
+///
+/// Arguments are numbered from 0, excluding this.
+pub struct Args<'a> {
+    state: &'a State,
+    function: &'a str,
+    len: usize,
+}
+
+impl<'a> Args<'a> {
+
+    /// The State of the call, for access to the stack
+    ///
+    /// this is at stack index 0 followed by the arguments.
+    pub fn state(self: &Args<'a>) -> &'a State {
+        self.state
+    }
+
+    /// Number of arguments, but at least the length the function was
+    /// registered with
+    ///
+    /// MuJS pads missing arguments with undefined up to the length,
+    /// so this is not the number of arguments passed by the caller
+    /// and `f()` can not be told apart from `f(undefined)`. Register
+    /// the function with length 0 to get the exact count, at the
+    /// cost of its `length` property being 0 as MuJS makes it read
+    /// only.
+    pub fn len(self: &Args<'a>) -> usize {
+        self.len
+    }
+
+    /// Convert this
+    pub fn this<T>(self: &Args<'a>) -> Result<T, CallError> where T: FromJs {
+        T::from_js(self.state, 0)
+            .map_err(|err| CallError::Type(format!("{}: this: {}", self.function, err)))
+    }
+
+    /// Convert argument i, arguments past the end are undefined
+    pub fn get<T>(self: &Args<'a>, i: usize) -> Result<T, CallError> where T: FromJs {
+        let res = match i < self.len {
+            true => T::from_js(self.state, i as i32 + 1),
+            false => {
+                self.state.pushundefined();
+                let res = T::from_js(self.state, -1);
+                self.state.pop(1);
+                res
+            }
+        };
+        res.map_err(|err| CallError::Type(format!("{}: parameter {}: {}", self.function, i + 1, err)))
+    }
+
+    /// Convert argument i, None if undefined or null
+    pub fn get_opt<T>(self: &Args<'a>, i: usize) -> Result<Option<T>, CallError> where T: FromJs {
+        self.get::<Option<T>>(i)
+    }
+
+    /// Convert the arguments starting at from
+    pub fn rest<T>(self: &Args<'a>, from: usize) -> Result<Vec<T>, CallError> where T: FromJs {
+        (from..self.len).map(|i| self.get(i)).collect()
+    }
+
+    /// Set the return value of the call, undefined unless set
+    pub fn ret<T>(self: &Args<'a>, value: T) where T: ToJs {
+        value.push_js(self.state);
+    }
+}
+
+impl State {
+
+    /// push a function object wrapping a rust closure taking Args
+    ///
+    /// A CallError returned by the closure is thrown into the
+    /// script, such as the TypeError returned by Args when an
+    /// argument has the wrong type.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use mujs;
+    ///
+    /// let state = mujs::State::new(mujs::JS_STRICT);
+    ///
+    /// state.new_function(|args| {
+    ///     let text: String = args.get(0)?;
+    ///     let times = args.get_opt::<u32>(1)?.unwrap_or(2);
+    ///     args.ret(text.repeat(times as usize));
+    ///     Ok(())
+    /// }, "repeat", 2).unwrap();
+    /// state.setglobal("repeat").unwrap();
+    ///
+    /// state.dostring("var result = repeat('ab');").unwrap();
+    /// state.getglobal("result").unwrap();
+    /// assert_eq!(state.tostring(-1).unwrap(), "abab");
+    ///
+    /// state.loadstring("bad.js", "repeat(1);").unwrap();
+    /// state.pushundefined();
+    /// assert_eq!(state.call(0).unwrap_err().message,
+    ///            "TypeError: repeat: parameter 1: expected a string, got number");
+    /// ```
+    pub fn new_function<F>(self: &State, mut func: F, name: &str, length: i32) -> Result<(), String>
+        where F: FnMut(&Args) -> Result<(), CallError> + Send + 'static
+    {
+        let function = name.to_string();
+        self.newfunction(move |js| {
+            let res = {
+                let args = Args {
+                    state: js,
+                    function: &function,
+                    len: (js.gettop() - 1).max(0) as usize,
+                };
+                func(&args)
+            };
+            // The message is dropped before throwing, which unwinds
+            // past this frame without running destructors. Nul bytes,
+            // for example echoed from script input, are escaped as
+            // they can not be passed to MuJS.
+            let pushed = match res {
+                Ok(()) => return,
+                Err(CallError::Type(message)) => js.newtypeerror(&message.replace('\0', "\\0")),
+                Err(CallError::Error(message)) => js.newerror(&message.replace('\0', "\\0"))
+            };
+            if let Err(message) = pushed {
+                js.pushstring(&message);
+            }
+            js.throw();
+        }, name, length)
+    }
+}
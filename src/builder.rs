@@ -0,0 +1,224 @@
+//! Fluent construction of objects and arrays on the stack
+
+use {State, PropertyAttributes, AsPropertyKey, ToJs};
+
+/// Builder for an object on top of stack
+///
+/// Created by State::object() which pushes a new empty object onto
+/// the stack. Each method adds a property to the object and
+/// ObjectBuilder::finish() leaves exactly the object on the stack. If any
+/// property could not be added the object is popped and the first
+/// error is returned by finish().
+///
+/// # Examples
+///
+/// ```
+/// use mujs;
+///
+/// let state = mujs::State::new(mujs::JS_STRICT);
+///
+/// state.object()
+///     .set("name", "Tester")
+///     .set("scores", vec![1, 2, 3])
+///     .define("id", 1234, mujs::JS_READONLY | mujs::JS_DONTENUM)
+///     .set_with("address", |state| {
+///         state.object()
+///             .set("city", "Gothenburg")
+///             .finish()
+///     })
+///     .finish()
+///     .unwrap();
+///
+/// assert_eq!(state.gettop(), 1);
+/// ```
+#[must_use]
+pub struct ObjectBuilder<'a> {
+    state: &'a State,
+    error: Option<String>,
+}
+
+impl<'a> ObjectBuilder<'a> {
+
+    fn _property<K, F>(mut self: ObjectBuilder<'a>, name: &K, func: F) -> ObjectBuilder<'a>
+        where K: AsPropertyKey + ?Sized,
+              F: FnOnce(&State, *const ::libc::c_char) -> Result<(), String>
+    {
+        if self.error.is_none() {
+            let state = self.state;
+            let res = name.with_key(|name| func(state, name));
+            if let Some(err) = res.and_then(|res| res).err() {
+                self.error = Some(err);
+            }
+        }
+        self
+    }
+
+    /// Set named property to value
+    pub fn set<K, V>(self: ObjectBuilder<'a>, name: &K, value: V) -> ObjectBuilder<'a>
+        where K: AsPropertyKey + ?Sized,
+              V: ToJs
+    {
+        self._property(name, |state, name| {
+            value.push_js(state);
+            unsafe { ::js_setproperty((*state.ptr).state, -2, name) };
+            Ok(())
+        })
+    }
+
+    /// Define named property with value and attributes
+    pub fn define<K, V>(self: ObjectBuilder<'a>, name: &K, value: V,
+                        attrs: PropertyAttributes) -> ObjectBuilder<'a>
+        where K: AsPropertyKey + ?Sized,
+              V: ToJs
+    {
+        self._property(name, |state, name| {
+            value.push_js(state);
+            unsafe { ::js_defproperty((*state.ptr).state, -2, name, attrs.bits) };
+            Ok(())
+        })
+    }
+
+    /// Set named property to the value pushed by a function
+    ///
+    /// The function must push exactly one value onto the stack,
+    /// typically using a nested builder.
+    pub fn set_with<K, F>(self: ObjectBuilder<'a>, name: &K, func: F) -> ObjectBuilder<'a>
+        where K: AsPropertyKey + ?Sized,
+              F: FnOnce(&State) -> Result<(), String>
+    {
+        self._property(name, |state, name| {
+            let top = state.gettop();
+            match (func(state), state.gettop() - top) {
+                (Ok(()), 1) => {
+                    unsafe { ::js_setproperty((*state.ptr).state, -2, name) };
+                    Ok(())
+                },
+                (res, n) => {
+                    if n > 0 {
+                        state.pop(n);
+                    }
+                    res.and_then(|_| {
+                        Err(format!("Expected one value pushed for property, got {}", n))
+                    })
+                }
+            }
+        })
+    }
+
+    /// Finish the object leaving it on top of stack
+    pub fn finish(self: ObjectBuilder<'a>) -> Result<(), String> {
+        match self.error {
+            None => Ok(()),
+            Some(err) => {
+                self.state.pop(1);
+                Err(err)
+            }
+        }
+    }
+}
+
+/// Builder for an array on top of stack
+///
+/// Created by State::array() which pushes a new empty array onto
+/// the stack. Items are appended in order and ArrayBuilder::finish()
+/// leaves exactly the array on the stack.
+///
+/// # Examples
+///
+/// ```
+/// use mujs;
+///
+/// let state = mujs::State::new(mujs::JS_STRICT);
+///
+/// state.array()
+///     .push(1.5)
+///     .push("two")
+///     .push_with(|state| state.array().push(3).finish())
+///     .finish()
+///     .unwrap();
+///
+/// assert_eq!(state.getlength(0), 3);
+/// ```
+#[must_use]
+pub struct ArrayBuilder<'a> {
+    state: &'a State,
+    length: i32,
+    error: Option<String>,
+}
+
+impl<'a> ArrayBuilder<'a> {
+
+    /// Append value to the array
+    pub fn push<V>(mut self: ArrayBuilder<'a>, value: V) -> ArrayBuilder<'a>
+        where V: ToJs
+    {
+        if self.error.is_none() {
+            value.push_js(self.state);
+            self.state.setindex(-2, self.length);
+            self.length += 1;
+        }
+        self
+    }
+
+    /// Append the value pushed by a function
+    ///
+    /// The function must push exactly one value onto the stack,
+    /// typically using a nested builder.
+    pub fn push_with<F>(mut self: ArrayBuilder<'a>, func: F) -> ArrayBuilder<'a>
+        where F: FnOnce(&State) -> Result<(), String>
+    {
+        if self.error.is_none() {
+            let top = self.state.gettop();
+            let res = func(self.state);
+            let n = self.state.gettop() - top;
+            match (res, n) {
+                (Ok(()), 1) => {
+                    self.state.setindex(-2, self.length);
+                    self.length += 1;
+                },
+                (res, n) => {
+                    if n > 0 {
+                        self.state.pop(n);
+                    }
+                    self.error = Some(res.err().unwrap_or_else(|| {
+                        format!("Expected one value pushed for array item, got {}", n)
+                    }));
+                }
+            }
+        }
+        self
+    }
+
+    /// Finish the array leaving it on top of stack
+    pub fn finish(self: ArrayBuilder<'a>) -> Result<(), String> {
+        match self.error {
+            None => Ok(()),
+            Some(err) => {
+                self.state.pop(1);
+                Err(err)
+            }
+        }
+    }
+}
+
+impl State {
+
+    /// Push a new object and return a builder for its properties
+    pub fn object<'a>(self: &'a State) -> ObjectBuilder<'a> {
+        self.newobject();
+        ObjectBuilder {
+            state: self,
+            error: None,
+        }
+    }
+
+    /// Push a new array and return a builder for its items
+    pub fn array<'a>(self: &'a State) -> ArrayBuilder<'a> {
+        self.newarray();
+        ArrayBuilder {
+            state: self,
+            length: 0,
+            error: None,
+        }
+    }
+}
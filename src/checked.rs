@@ -0,0 +1,243 @@
+//! Stack methods taking a validated StackIndex
+
+use {State, StackIndex, AsPropertyKey, PropertyAttributes};
+
+macro_rules! checked_test {
+    ($($(#[$attr:meta])* $name:ident => $raw:ident),* $(,)*) => ($(
+        $(#[$attr])*
+        pub fn $name(self: &State, idx: StackIndex) -> Result<bool, String> {
+            Ok(self.$raw(self.abs_index(idx)?))
+        }
+    )*)
+}
+
+macro_rules! checked_conversion {
+    ($($(#[$attr:meta])* $name:ident => $raw:ident -> $t:ty),* $(,)*) => ($(
+        $(#[$attr])*
+        pub fn $name(self: &State, idx: StackIndex) -> Result<$t, String> {
+            self.$raw(self.abs_index(idx)?)
+        }
+    )*)
+}
+
+impl State {
+
+    /// Validate stack index of a value which has properties, which
+    /// is anything but undefined and null
+    fn _object_index(self: &State, idx: StackIndex) -> Result<i32, String> {
+        let idx = self.abs_index(idx)?;
+        match self.isundefined(idx) || self.isnull(idx) {
+            true => Err(format!("Value at stack index {} has no properties", idx)),
+            false => Ok(idx)
+        }
+    }
+
+    /// Validate stack index of a value which has properties and has
+    /// at least n items above it to be popped
+    fn _object_index_below(self: &State, idx: StackIndex, n: i32) -> Result<i32, String> {
+        let idx = self._object_index(idx)?;
+        match self.gettop() - idx > n {
+            true => Ok(idx),
+            false => Err(format!("Expected {} items above stack index {}", n, idx))
+        }
+    }
+
+    /// Copy stack item at validated index and push on top of stack
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use mujs;
+    /// use mujs::StackIndex::{Absolute, Top};
+    ///
+    /// let state = mujs::State::new(mujs::JS_STRICT);
+    /// state.pushnumber(1.0);
+    ///
+    /// state.copy_at(Top(1)).unwrap();
+    /// assert_eq!(state.tonumber_at(Absolute(1)).unwrap(), 1.0);
+    ///
+    /// assert!(state.copy_at(Absolute(2)).is_err());
+    /// assert_eq!(state.gettop(), 2);
+    /// ```
+    pub fn copy_at(self: &State, idx: StackIndex) -> Result<(), String> {
+        self.copy(self.abs_index(idx)?);
+        Ok(())
+    }
+
+    /// Remove item at validated index from stack
+    pub fn remove_at(self: &State, idx: StackIndex) -> Result<(), String> {
+        self.remove(self.abs_index(idx)?);
+        Ok(())
+    }
+
+    checked_test! {
+        /// Test if item at validated stack index is not undefined
+        isdefined_at => isdefined,
+        /// Test if item at validated stack index is undefined
+        isundefined_at => isundefined,
+        /// Test if item at validated stack index is null
+        isnull_at => isnull,
+        /// Test if item at validated stack index is a boolean
+        isboolean_at => isboolean,
+        /// Test if item at validated stack index is a number
+        isnumber_at => isnumber,
+        /// Test if item at validated stack index is a string
+        isstring_at => isstring,
+        /// Test if item at validated stack index is a primitive
+        isprimitive_at => isprimitive,
+        /// Test if item at validated stack index is an object
+        isobject_at => isobject,
+        /// Test if item at validated stack index is an array
+        isarray_at => isarray,
+        /// Test if item at validated stack index is callable
+        iscallable_at => iscallable,
+        /// Test if item at validated stack index is a regular
+        /// expression
+        isregexp_at => isregexp,
+    }
+
+    checked_conversion! {
+        /// Convert item at validated stack index to a string, see
+        /// State::tostring()
+        tostring_at => tostring -> String,
+        /// Convert item at validated stack index to a string without
+        /// replacing invalid characters, see State::to_str()
+        to_str_at => to_str -> String,
+        /// Convert item at validated stack index to bytes, see
+        /// State::to_bytes()
+        to_bytes_at => to_bytes -> Vec<u8>,
+        /// Convert item at validated stack index to a boolean
+        toboolean_at => toboolean -> bool,
+        /// Convert item at validated stack index to a number
+        tonumber_at => tonumber -> f64,
+        /// Convert item at validated stack index to an integer
+        tointeger_at => tointeger -> i32,
+    }
+
+    /// Get the `typeof` name of item at validated stack index
+    pub fn type_of_at(self: &State, idx: StackIndex) -> Result<String, String> {
+        Ok(self.type_of(self.abs_index(idx)?))
+    }
+
+    /// Test if object at validated stack index has named property
+    pub fn hasproperty_at<K>(self: &State, idx: StackIndex, name: &K) -> Result<bool, String>
+        where K: AsPropertyKey + ?Sized
+    {
+        self.hasproperty(self._object_index(idx)?, name)
+    }
+
+    /// Push the value of named property of object at validated stack
+    /// index on top of stack
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use mujs;
+    /// use mujs::StackIndex::Top;
+    ///
+    /// let state = mujs::State::new(mujs::JS_STRICT);
+    /// state.pushundefined();
+    /// assert!(state.getproperty_at(Top(1), "name").is_err());
+    ///
+    /// state.newobject();
+    /// state.pushstring("Tester");
+    /// state.setproperty_at(Top(2), "name").unwrap();
+    ///
+    /// state.getproperty_at(Top(1), "name").unwrap();
+    /// assert_eq!(state.tostring_at(Top(1)).unwrap(), "Tester");
+    /// ```
+    pub fn getproperty_at<K>(self: &State, idx: StackIndex, name: &K) -> Result<(), String>
+        where K: AsPropertyKey + ?Sized
+    {
+        self.getproperty(self._object_index(idx)?, name)
+    }
+
+    /// Pop item on top of stack and set it as named property of
+    /// object at validated stack index
+    ///
+    /// Fails if there is no item above the object.
+    pub fn setproperty_at<K>(self: &State, idx: StackIndex, name: &K) -> Result<(), String>
+        where K: AsPropertyKey + ?Sized
+    {
+        self.setproperty(self._object_index_below(idx, 1)?, name)
+    }
+
+    /// Pop item on top of stack and define it as named property of
+    /// object at validated stack index
+    ///
+    /// Fails if there is no item above the object.
+    pub fn defproperty_at<K>(self: &State, idx: StackIndex, name: &K,
+                             attrs: PropertyAttributes) -> Result<(), String>
+        where K: AsPropertyKey + ?Sized
+    {
+        self.defproperty(self._object_index_below(idx, 1)?, name, attrs)
+    }
+
+    /// Pop getter and setter functions and define them as accessor
+    /// of named property of object at validated stack index
+    ///
+    /// Fails if there are not two items above the object.
+    pub fn defaccessor_at<K>(self: &State, idx: StackIndex, name: &K,
+                             attrs: PropertyAttributes) -> Result<(), String>
+        where K: AsPropertyKey + ?Sized
+    {
+        self.defaccessor(self._object_index_below(idx, 2)?, name, attrs)
+    }
+
+    /// Delete named property of object at validated stack index
+    pub fn delproperty_at<K>(self: &State, idx: StackIndex, name: &K) -> Result<(), String>
+        where K: AsPropertyKey + ?Sized
+    {
+        self.delproperty(self._object_index(idx)?, name)
+    }
+
+    /// Get the length property of object at validated stack index
+    pub fn getlength_at(self: &State, idx: StackIndex) -> Result<i32, String> {
+        Ok(self.getlength(self._object_index(idx)?))
+    }
+
+    /// Set the length property of object at validated stack index
+    pub fn setlength_at(self: &State, idx: StackIndex, length: i32) -> Result<(), String> {
+        self.setlength(self._object_index(idx)?, length);
+        Ok(())
+    }
+
+    /// Test if object at validated stack index has array index i
+    pub fn hasindex_at(self: &State, idx: StackIndex, i: i32) -> Result<bool, String> {
+        Ok(self.hasindex(self._object_index(idx)?, i))
+    }
+
+    /// Push item at array index i of object at validated stack index
+    pub fn getindex_at(self: &State, idx: StackIndex, i: i32) -> Result<(), String> {
+        self.getindex(self._object_index(idx)?, i);
+        Ok(())
+    }
+
+    /// Pop item on top of stack and set it at array index i of object
+    /// at validated stack index
+    ///
+    /// Fails if there is no item above the object.
+    pub fn setindex_at(self: &State, idx: StackIndex, i: i32) -> Result<(), String> {
+        self.setindex(self._object_index_below(idx, 1)?, i);
+        Ok(())
+    }
+
+    /// Delete array index i of object at validated stack index
+    pub fn delindex_at(self: &State, idx: StackIndex, i: i32) -> Result<(), String> {
+        self.delindex(self._object_index(idx)?, i);
+        Ok(())
+    }
+
+    /// Push an iterator over the property names of object at
+    /// validated stack index, see State::pushiterator()
+    pub fn pushiterator_at(self: &State, idx: StackIndex, own: bool) -> Result<(), String> {
+        self.pushiterator(self._object_index(idx)?, own);
+        Ok(())
+    }
+
+    /// Get the next property name from the iterator at validated
+    /// stack index, see State::nextiterator()
+    pub fn nextiterator_at(self: &State, idx: StackIndex) -> Result<Option<String>, String> {
+        Ok(self.nextiterator(self.abs_index(idx)?))
+    }
+}
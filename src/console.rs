@@ -0,0 +1,224 @@
+//! console object routed to a Rust sink
+
+use State;
+
+/// Script returning a function which creates the console object
+/// around a native write function
+///
+/// Formatting follows the console of browsers, a leading string
+/// argument may contain the specifiers `%s`, `%d`, `%i`, `%f`, `%o`,
+/// `%O`, `%c` and `%%`. Arguments without a specifier are appended
+/// separated by spaces.
+static CONSOLE_SOURCE: &str = "
+(function (write) {
+    var LOG = 0, INFO = 1, WARN = 2, ERROR = 3, DEBUG = 4, TRACE = 5;
+    var timers = {};
+    function inspect(v) {
+        if (typeof v === 'string')
+            return v;
+        if (v !== null && typeof v === 'object') {
+            try {
+                var s = JSON.stringify(v);
+                if (s !== undefined)
+                    return s;
+            } catch (e) {}
+        }
+        return String(v);
+    }
+    function integer(v) {
+        var n = Number(v);
+        return String(n < 0 ? Math.ceil(n) : Math.floor(n));
+    }
+    function format(args) {
+        var out = [], i = 0, fmt;
+        if (typeof args[0] === 'string') {
+            fmt = args[0];
+            i = 1;
+            out.push(fmt.replace(/%([sdifoOc%])/g, function (m, c) {
+                if (c === '%')
+                    return '%';
+                if (i >= args.length)
+                    return m;
+                var v = args[i++];
+                switch (c) {
+                case 's': return String(v);
+                case 'd': case 'i': return integer(v);
+                case 'f': return String(Number(v));
+                case 'c': return '';
+                default: return inspect(v);
+                }
+            }));
+        }
+        for (; i < args.length; ++i)
+            out.push(inspect(args[i]));
+        return out.join(' ');
+    }
+    function logger(level) {
+        return function () { write(level, format(arguments)); };
+    }
+    function label(v) {
+        return v === undefined ? 'default' : String(v);
+    }
+    return {
+        log: logger(LOG),
+        info: logger(INFO),
+        warn: logger(WARN),
+        error: logger(ERROR),
+        debug: logger(DEBUG),
+        trace: function () {
+            var message = arguments.length > 0 ? 'Trace: ' + format(arguments) : 'Trace';
+            var e = new Error();
+            var stack = e.stack || e.stackTrace;
+            write(TRACE, stack ? message + '\\n' + stack : message);
+        },
+        assert: function (condition) {
+            var args;
+            if (condition)
+                return;
+            args = Array.prototype.slice.call(arguments, 1);
+            write(ERROR, args.length > 0 ? 'Assertion failed: ' + format(args) : 'Assertion failed');
+        },
+        time: function (name) {
+            name = label(name);
+            if (timers.hasOwnProperty(name))
+                write(WARN, \"Timer '\" + name + \"' already exists\");
+            else
+                timers[name] = Date.now();
+        },
+        timeEnd: function (name) {
+            name = label(name);
+            if (!timers.hasOwnProperty(name)) {
+                write(WARN, \"Timer '\" + name + \"' does not exist\");
+                return;
+            }
+            write(LOG, name + ': ' + (Date.now() - timers[name]) + 'ms');
+            delete timers[name];
+        }
+    };
+})";
+
+/// Severity of a console message
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ConsoleLevel {
+    /// console.log() and console.timeEnd()
+    Log,
+    /// console.info()
+    Info,
+    /// console.warn()
+    Warn,
+    /// console.error() and failed console.assert()
+    Error,
+    /// console.debug()
+    Debug,
+    /// console.trace()
+    Trace,
+}
+
+impl ConsoleLevel {
+
+    fn from_number(level: f64) -> ConsoleLevel {
+        match level as i32 {
+            1 => ConsoleLevel::Info,
+            2 => ConsoleLevel::Warn,
+            3 => ConsoleLevel::Error,
+            4 => ConsoleLevel::Debug,
+            5 => ConsoleLevel::Trace,
+            _ => ConsoleLevel::Log
+        }
+    }
+}
+
+/// Receiver of formatted console messages
+///
+/// Implemented for closures taking the level and message.
+pub trait ConsoleSink {
+    /// Handle a formatted message
+    fn write(&mut self, level: ConsoleLevel, message: &str);
+}
+
+impl<F> ConsoleSink for F where F: FnMut(ConsoleLevel, &str) {
+    fn write(self: &mut F, level: ConsoleLevel, message: &str) {
+        self(level, message)
+    }
+}
+
+/// Console sink writing warnings and errors to stderr and all
+/// other messages to stdout
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StdioSink;
+
+impl ConsoleSink for StdioSink {
+    fn write(self: &mut StdioSink, level: ConsoleLevel, message: &str) {
+        match level {
+            ConsoleLevel::Warn | ConsoleLevel::Error => eprintln!("{}", message),
+            _ => println!("{}", message)
+        }
+    }
+}
+
+/// Console sink forwarding messages to the `log` crate
+///
+/// Messages are logged with target `console`, console.log() and
+/// console.info() map to the info level.
+#[cfg(feature = "log")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LogSink;
+
+#[cfg(feature = "log")]
+impl ConsoleSink for LogSink {
+    fn write(self: &mut LogSink, level: ConsoleLevel, message: &str) {
+        let level = match level {
+            ConsoleLevel::Log | ConsoleLevel::Info => ::log::Level::Info,
+            ConsoleLevel::Warn => ::log::Level::Warn,
+            ConsoleLevel::Error => ::log::Level::Error,
+            ConsoleLevel::Debug => ::log::Level::Debug,
+            ConsoleLevel::Trace => ::log::Level::Trace,
+        };
+        log!(target: "console", level, "{}", message);
+    }
+}
+
+impl State {
+
+    /// Install a global console object writing to sink
+    ///
+    /// Provides `console.log`, `info`, `warn`, `error`, `debug`,
+    /// `trace`, `assert`, `time` and `timeEnd` with printf-style
+    /// formatting of the arguments. Installing a console replaces
+    /// any previously installed one.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use mujs;
+    /// use std::sync::{Arc, Mutex};
+    ///
+    /// let state = mujs::State::new(mujs::JS_STRICT);
+    /// let messages = Arc::new(Mutex::new(Vec::new()));
+    ///
+    /// let sink = messages.clone();
+    /// state.install_console(move |level, message: &str| {
+    ///     sink.lock().unwrap().push((level, message.to_string()));
+    /// }).unwrap();
+    ///
+    /// state.dostring("console.warn('%s is %d years', 'Bob', 42.5);").unwrap();
+    ///
+    /// assert_eq!(messages.lock().unwrap()[0],
+    ///            (mujs::ConsoleLevel::Warn, "Bob is 42 years".to_string()));
+    /// ```
+    pub fn install_console<S>(self: &State, mut sink: S) -> Result<(), String>
+        where S: ConsoleSink + Send + 'static
+    {
+        self.loadstring("[console]", CONSOLE_SOURCE)?;
+        self.pushundefined();
+        self.call(0)?;
+        self.pushundefined();
+        self.newfunction(move |js| {
+            let level = ConsoleLevel::from_number(js.tonumber(1).unwrap_or(0.0));
+            let message = js.tostring(2).unwrap_or_default();
+            sink.write(level, &message);
+        }, "write", 2)?;
+        self.call(1)?;
+        self.setglobal("console")
+    }
+}
@@ -0,0 +1,102 @@
+//! Constructors creating instances of a prototype
+
+use State;
+
+/// Registry entry holding the original Object.create, unaffected by
+/// scripts replacing the global
+static OBJECT_CREATE_REGISTRY_NAME: &str = "__RustObjectCreate__";
+
+impl State {
+
+    /// Replace the null this of a constructor call with a new object
+    /// inheriting from the prototype of the current function
+    ///
+    /// On failure the thrown value is left on top of stack.
+    fn _construct_this(self: &State) -> Result<(), String> {
+        self._getregistry(OBJECT_CREATE_REGISTRY_NAME)?;
+        self.pushundefined();
+        unsafe { ::js_currentfunction((*self.ptr).state) };
+        self.getproperty(-1, "prototype")?;
+        self.remove(-2);
+        self.call(1)?;
+        unsafe { ::js_replace((*self.ptr).state, 0) };
+        Ok(())
+    }
+
+    /// push a constructor function object with a prototype for its
+    /// instances
+    ///
+    /// The call closure is invoked when called as a function, as
+    /// with State::newfunction(). When called with `new` the
+    /// construct closure is invoked with a new object inheriting
+    /// from the prototype as this, which is returned unless the
+    /// closure pushes another value. Instances thus pass
+    /// `instanceof` checks.
+    ///
+    /// The prototype is a plain object available as the prototype
+    /// property of the constructor, where methods shared by the
+    /// instances can be defined.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use mujs;
+    ///
+    /// let state = mujs::State::new(mujs::JS_STRICT);
+    ///
+    /// state.new_constructor(|js| {
+    ///     js.pushstring("Point must be called with new");
+    /// }, |js| {
+    ///     js.copy(1);
+    ///     js.setproperty(0, "x").unwrap();
+    ///     js.copy(2);
+    ///     js.setproperty(0, "y").unwrap();
+    /// }, "Point", 2).unwrap();
+    ///
+    /// state.getproperty(-1, "prototype").unwrap();
+    /// state.newfunction(|js| {
+    ///     js.getproperty(0, "x").unwrap();
+    ///     js.getproperty(0, "y").unwrap();
+    ///     js.concat();
+    /// }, "sum", 0).unwrap();
+    /// state.setproperty(-2, "sum").unwrap();
+    /// state.pop(1);
+    /// state.setglobal("Point").unwrap();
+    ///
+    /// state.dostring("var p = new Point(1, 2), ok = p instanceof Point;").unwrap();
+    /// state.getglobal("ok").unwrap();
+    /// assert_eq!(state.toboolean(-1).unwrap(), true);
+    ///
+    /// state.dostring("var sum = p.sum();").unwrap();
+    /// state.getglobal("sum").unwrap();
+    /// assert_eq!(state.tonumber(-1).unwrap(), 3.0);
+    /// ```
+    pub fn new_constructor<F, C>(self: &State, call: F, mut construct: C, name: &str,
+                                 length: i32) -> Result<(), String>
+        where F: FnMut(&State) + Send + 'static,
+              C: FnMut(&State) + Send + 'static
+    {
+        self._getregistry(OBJECT_CREATE_REGISTRY_NAME)?;
+        let initialized = !self.isundefined(-1);
+        self.pop(1);
+        if !initialized {
+            self.getglobal("Object")?;
+            self.getproperty(-1, "create")?;
+            self.remove(-2);
+            self._setregistry(OBJECT_CREATE_REGISTRY_NAME)?;
+        }
+
+        self.newobject();
+        self.newcconstructor(call, move |js| {
+            if js._construct_this().is_err() {
+                js.throw();
+                return;
+            }
+            let top = js.gettop();
+            construct(js);
+            if js.gettop() == top {
+                js.copy(0);
+            }
+        }, name, length)
+    }
+}
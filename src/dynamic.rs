@@ -0,0 +1,130 @@
+//! Objects with properties resolved by Rust at access time
+
+use State;
+
+/// Tag of userdata objects created by State::push_dynamic()
+static DYNAMIC_TAG: &str = "__RustDynamicObject__";
+
+/// Host data exposed to scripts as an object whose properties are
+/// resolved on each access
+///
+/// Properties the object does not handle fall back to ordinary own
+/// properties and the prototype, Object.prototype, so that methods
+/// such as toString() keep working. Accesses made while a method of
+/// the same object is running, for example from a script called by
+/// get(), also fall back.
+///
+/// # Examples
+///
+/// ```
+/// use mujs;
+/// use std::collections::HashMap;
+/// use std::sync::{Arc, Mutex};
+///
+/// struct Env(Arc<Mutex<HashMap<String, String>>>);
+///
+/// impl mujs::DynamicObject for Env {
+///     fn has(&self, name: &str) -> bool {
+///         self.0.lock().unwrap().contains_key(name)
+///     }
+///
+///     fn get(&mut self, state: &mujs::State, name: &str) {
+///         state.pushstring(&self.0.lock().unwrap()[name]);
+///     }
+///
+///     fn set(&mut self, state: &mujs::State, name: &str) -> bool {
+///         let value = state.tostring(-1).unwrap();
+///         self.0.lock().unwrap().insert(name.to_string(), value);
+///         true
+///     }
+///
+///     fn delete(&mut self, _: &mujs::State, name: &str) -> bool {
+///         self.0.lock().unwrap().remove(name).is_some()
+///     }
+/// }
+///
+/// let store = Arc::new(Mutex::new(HashMap::new()));
+/// store.lock().unwrap().insert("HOME".to_string(), "/home/tester".to_string());
+///
+/// let state = mujs::State::new(mujs::JS_STRICT);
+/// state.push_dynamic(Env(store.clone())).unwrap();
+/// state.setglobal("env").unwrap();
+///
+/// state.dostring("env.PATH = env.HOME + '/bin'; delete env.HOME;").unwrap();
+/// assert_eq!(store.lock().unwrap()["PATH"], "/home/tester/bin");
+/// assert!(!store.lock().unwrap().contains_key("HOME"));
+/// ```
+pub trait DynamicObject: Send + 'static {
+    /// Test if name is a property handled by the object
+    fn has(&self, name: &str) -> bool;
+
+    /// Push the value of property name, only called when has()
+    /// returned true
+    ///
+    /// Pushing nothing yields undefined.
+    fn get(&mut self, state: &State, name: &str);
+
+    /// Assign the value on top of stack to property name, leaving it
+    /// on the stack
+    ///
+    /// Returns false to store the value as an ordinary property of
+    /// the object instead, which is the default.
+    fn set(&mut self, state: &State, name: &str) -> bool {
+        let _ = (state, name);
+        false
+    }
+
+    /// Delete property name
+    ///
+    /// Returns false to delete an ordinary property of the object
+    /// instead, which is the default.
+    fn delete(&mut self, state: &State, name: &str) -> bool {
+        let _ = (state, name);
+        false
+    }
+}
+
+impl State {
+
+    /// Push an object whose properties are resolved by a
+    /// DynamicObject
+    ///
+    /// The DynamicObject is dropped when the object is garbage
+    /// collected, use State::with_dynamic() to access it from Rust.
+    pub fn push_dynamic<T>(self: &State, object: T) -> Result<(), String> where T: DynamicObject {
+        self.pushnull();
+        self._newdynamic(object)
+    }
+
+    /// Pop the prototype object on top of stack and push an object
+    /// whose properties are resolved by a DynamicObject
+    pub(crate) fn _newdynamic<T>(self: &State, object: T) -> Result<(), String> where T: DynamicObject {
+        self.newuserdatax(DYNAMIC_TAG, object, |js, object: &mut T, name| {
+            if !object.has(name) {
+                return false;
+            }
+            let top = js.gettop();
+            object.get(js, name);
+            if js.gettop() == top {
+                js.pushundefined();
+            }
+            true
+        }, |js, object, name| {
+            object.set(js, name)
+        }, |js, object, name| {
+            object.delete(js, name)
+        })
+    }
+
+    /// Call func with the DynamicObject of type T behind the object
+    /// at stack index
+    ///
+    /// Fails if the item was not created by State::push_dynamic()
+    /// with a T.
+    pub fn with_dynamic<T, R, F>(self: &State, idx: i32, func: F) -> Result<R, String>
+        where T: DynamicObject,
+              F: FnOnce(&mut T) -> R
+    {
+        self.with_userdata(idx, DYNAMIC_TAG, func)
+    }
+}
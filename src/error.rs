@@ -0,0 +1,337 @@
+//! Errors reported by the Javascript engine
+
+use std::error::Error;
+use std::fmt;
+
+use State;
+
+/// Error thrown by a script or reported by MuJS
+///
+/// Converts from and into String so that it can be used with `?`
+/// alongside methods returning `Result<_, String>`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JsError {
+    /// Message as reported by MuJS, typically the name of the error
+    /// followed by its message such as `TypeError: x is undefined`
+    pub message: String,
+    /// Name of the script where the error occurred, if known
+    pub file: Option<String>,
+    /// Line in the script where the error occurred, if known
+    pub line: Option<u32>,
+    /// Column in the line where the error occurred, if known
+    ///
+    /// MuJS itself only reports lines, columns are picked up from
+    /// messages of the form `file:line:column: message`.
+    pub column: Option<u32>,
+    /// Call chain at the point the Error object was created,
+    /// innermost frame first
+    ///
+    /// Empty if the thrown value was not an Error object or the
+    /// error was raised while compiling.
+    pub stack: Vec<StackFrame>,
+}
+
+/// Frame of the call chain of a JsError
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StackFrame {
+    /// Name of the called function, None for anonymous functions
+    pub function: Option<String>,
+    /// Name of the script defining the function, `native` for
+    /// functions implemented in C or Rust
+    pub file: String,
+    /// Line in the script of the call, None for native functions
+    pub line: Option<u32>,
+}
+
+impl JsError {
+
+    /// Constructs a new JsError with message
+    pub fn new<S>(message: S) -> JsError where S: Into<String> {
+        JsError {
+            message: message.into(),
+            file: None,
+            line: None,
+            column: None,
+            stack: Vec::new(),
+        }
+    }
+
+    /// Constructs a JsError from a message reported by MuJS,
+    /// extracting the location of errors raised while compiling
+    ///
+    /// Compile errors are reported as `SyntaxError: file:line:
+    /// message`, the messages of other errors are left alone as
+    /// they may contain text of the same form.
+    pub fn from_message<S>(message: S) -> JsError where S: Into<String> {
+        let mut err = JsError::new(message);
+        if let Some((file, line, column)) = parse_location(&err.message) {
+            err.file = Some(file.to_string());
+            err.line = Some(line);
+            err.column = column;
+        }
+        err
+    }
+
+    /// Constructs a JsError from a message and the `stack` property
+    /// of the thrown Error object
+    ///
+    /// The location in the message of a compile error takes
+    /// precedence, otherwise the innermost script frame of the stack
+    /// is used.
+    pub fn from_stack<S>(message: S, stack: &str) -> JsError where S: Into<String> {
+        let mut err = JsError::from_message(message);
+        err.stack = stack.lines()
+            .filter_map(parse_frame)
+            .map(|(function, file, line)| StackFrame {
+                function: function.map(|function| function.to_string()),
+                file: file.to_string(),
+                line,
+            })
+            .collect();
+
+        if err.line.is_none() {
+            let location = err.stack.iter()
+                .filter_map(|frame| frame.line.map(|line| (frame.file.clone(), line)))
+                .next();
+            if let Some((file, line)) = location {
+                err.file = Some(file);
+                err.line = Some(line);
+            }
+        }
+        err
+    }
+
+    /// Render the lines of source around the error with a caret
+    /// marking the location
+    ///
+    /// Returns None if the line of the error is unknown or outside
+    /// of source. Without a column the whole line is underlined.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use mujs;
+    ///
+    /// let source = "var a = 1;\nvar = 2;\nvar b = 3;";
+    /// let err = mujs::JsError::from_message("SyntaxError: my.js:2:5: unexpected token");
+    ///
+    /// assert_eq!(err.excerpt(source).unwrap(),
+    ///            "1 | var a = 1;\n2 | var = 2;\n  |     ^\n3 | var b = 3;\n");
+    /// ```
+    pub fn excerpt(self: &JsError, source: &str) -> Option<String> {
+        let line = self.line? as usize;
+        let lines: Vec<&str> = source.lines().collect();
+        if line == 0 || line > lines.len() {
+            return None;
+        }
+
+        let first = if line > 1 { line - 1 } else { line };
+        let last = if line < lines.len() { line + 1 } else { line };
+        let width = last.to_string().len();
+
+        let mut out = String::new();
+        for n in first..last + 1 {
+            let text = lines[n - 1];
+            out.push_str(&format!("{:>width$} | {}\n", n, text, width = width));
+            if n == line {
+                let (offset, length) = match self.column {
+                    Some(column) if column > 0 => (column as usize - 1, 1),
+                    _ => {
+                        let trimmed = text.trim_start();
+                        (text.len() - trimmed.len(), trimmed.trim_end().chars().count().max(1))
+                    }
+                };
+                out.push_str(&format!("{:>width$} | {}{}\n", "", " ".repeat(offset),
+                                      "^".repeat(length), width = width));
+            }
+        }
+        Some(out)
+    }
+}
+
+/// Parse a number which must consist of digits only
+fn parse_number(value: &str) -> Option<u32> {
+    match !value.is_empty() && value.chars().all(|c| c.is_ascii_digit()) {
+        true => value.parse().ok(),
+        false => None
+    }
+}
+
+/// Find the `file:line: ` or `file:line:column: ` location following
+/// `SyntaxError: ` at the start of message
+fn parse_location(message: &str) -> Option<(&str, u32, Option<u32>)> {
+    let prefix = "SyntaxError: ";
+    if !message.starts_with(prefix) {
+        return None;
+    }
+    let rest = &message[prefix.len()..];
+    let location = &rest[..rest.find(": ")?];
+    let colon = location.rfind(':')?;
+    let number = parse_number(&location[colon + 1..])?;
+
+    let file = &location[..colon];
+    if let Some(colon) = file.rfind(':') {
+        if let Some(line) = parse_number(&file[colon + 1..]) {
+            return Some((&file[..colon], line, Some(number)));
+        }
+    }
+    Some((file, number, None))
+}
+
+/// Parse a line of the `stack` property
+///
+/// MuJS formats frames as `\tat function (file:line)`, `\tat
+/// file:line` for anonymous functions and `\tat function (native)`
+/// for native functions, which have no line.
+fn parse_frame(frame: &str) -> Option<(Option<&str>, &str, Option<u32>)> {
+    let frame = frame.trim();
+    if !frame.starts_with("at ") {
+        return None;
+    }
+    let frame = &frame[3..];
+
+    let (function, location) = match frame.ends_with(')') {
+        true => {
+            let open = frame.rfind(" (")?;
+            (Some(&frame[..open]), &frame[open + 2..frame.len() - 1])
+        },
+        false => (None, frame)
+    };
+
+    match location.rfind(':') {
+        Some(colon) => match parse_number(&location[colon + 1..]) {
+            Some(line) => Some((function, &location[..colon], Some(line))),
+            None => Some((function, location, None))
+        },
+        None => Some((function, location, None))
+    }
+}
+
+impl State {
+
+    /// Convert the thrown value on top of stack into a JsError,
+    /// leaving it on the stack
+    pub(crate) fn _error_at_top(self: &State) -> JsError {
+        let message = self.tostring(-1).unwrap_or_default();
+
+        let mut stack = None;
+        if self.isobject(-1) {
+            for name in &["stack", "stackTrace"] {
+                if self.getproperty(-1, *name).is_ok() {
+                    if self.isstring(-1) {
+                        stack = self.tostring(-1).ok();
+                    }
+                    self.pop(1);
+                }
+                if stack.is_some() {
+                    break;
+                }
+            }
+        }
+
+        match stack {
+            Some(stack) => JsError::from_stack(message, &stack),
+            None => JsError::from_message(message)
+        }
+    }
+}
+
+impl fmt::Display for StackFrame {
+    fn fmt(self: &StackFrame, f: &mut fmt::Formatter) -> fmt::Result {
+        let function = self.function.as_ref().map_or("<anonymous>", |function| function.as_str());
+        match self.line {
+            Some(line) => write!(f, "at {} ({}:{})", function, self.file, line),
+            None => write!(f, "at {} ({})", function, self.file)
+        }
+    }
+}
+
+impl fmt::Display for JsError {
+    fn fmt(self: &JsError, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl Error for JsError {
+}
+
+impl From<String> for JsError {
+    fn from(message: String) -> JsError {
+        JsError::new(message)
+    }
+}
+
+impl From<JsError> for String {
+    fn from(err: JsError) -> String {
+        err.message
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{JsError, StackFrame};
+
+    #[test]
+    fn from_message_with_location() {
+        let err = JsError::from_message("SyntaxError: dir/my:script.js:12: unexpected token in expression: '='");
+        assert_eq!(err.file.unwrap(), "dir/my:script.js");
+        assert_eq!(err.line, Some(12));
+        assert_eq!(err.column, None);
+    }
+
+    #[test]
+    fn from_message_with_column() {
+        let err = JsError::from_message("SyntaxError: script.js:12:7: unexpected token");
+        assert_eq!(err.file.unwrap(), "script.js");
+        assert_eq!(err.line, Some(12));
+        assert_eq!(err.column, Some(7));
+    }
+
+    #[test]
+    fn from_message_without_location() {
+        let err = JsError::from_message("TypeError: undefined is not callable");
+        assert_eq!(err.file, None);
+        assert_eq!(err.line, None);
+        assert_eq!(err.message, "TypeError: undefined is not callable");
+    }
+
+    #[test]
+    fn from_stack_uses_innermost_script_frame() {
+        let err = JsError::from_stack("Error: Boom",
+                                      "\n\tat print (native)\n\tat inner (lib.js:7)\n\tat main.js:2");
+        assert_eq!(err.file.unwrap(), "lib.js");
+        assert_eq!(err.line, Some(7));
+        assert_eq!(err.stack, vec![
+            StackFrame { function: Some("print".to_string()), file: "native".to_string(), line: None },
+            StackFrame { function: Some("inner".to_string()), file: "lib.js".to_string(), line: Some(7) },
+            StackFrame { function: None, file: "main.js".to_string(), line: Some(2) },
+        ]);
+        assert_eq!(err.stack[1].to_string(), "at inner (lib.js:7)");
+        assert_eq!(err.stack[2].to_string(), "at <anonymous> (main.js:2)");
+    }
+
+    #[test]
+    fn from_stack_ignores_location_like_message() {
+        let err = JsError::from_stack("Error: retry at 10:30: timeout", "\n\tat main.js:4");
+        assert_eq!(err.file.unwrap(), "main.js");
+        assert_eq!(err.line, Some(4));
+        assert_eq!(err.column, None);
+
+        let err = JsError::from_message("Error: retry at 10:30: timeout");
+        assert_eq!(err.file, None);
+        assert_eq!(err.line, None);
+    }
+
+    #[test]
+    fn excerpt_without_column_underlines_line() {
+        let err = JsError::from_message("SyntaxError: a.js:1: unexpected end of file");
+        assert_eq!(err.excerpt("  foo(\nbar").unwrap(), "1 |   foo(\n  |   ^^^^\n2 | bar\n");
+    }
+
+    #[test]
+    fn excerpt_outside_source_is_none() {
+        let err = JsError::from_message("SyntaxError: a.js:3: unexpected end of file");
+        assert_eq!(err.excerpt("one line"), None);
+        assert_eq!(JsError::new("Error").excerpt("one line"), None);
+    }
+}
@@ -0,0 +1,351 @@
+//! Timers and microtasks for scripts written for browsers
+
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use State;
+
+struct Timer {
+    id: u32,
+    seq: u64,
+    due: Instant,
+    interval: Option<Duration>,
+}
+
+struct Timers {
+    next_seq: u64,
+    virtual_now: Option<Instant>,
+    timers: Vec<Timer>,
+}
+
+impl Timers {
+
+    fn now(self: &Timers) -> Instant {
+        self.virtual_now.unwrap_or_else(Instant::now)
+    }
+
+    /// Index of the timer which is due first, timers due at the same
+    /// time are ordered by when they were scheduled
+    fn next(self: &Timers) -> Option<usize> {
+        self.timers.iter()
+            .enumerate()
+            .min_by_key(|&(_, timer)| (timer.due, timer.seq))
+            .map(|(i, _)| i)
+    }
+
+    fn remove(self: &mut Timers, id: u32) -> bool {
+        match self.timers.iter().position(|timer| timer.id == id) {
+            Some(i) => {
+                self.timers.remove(i);
+                true
+            },
+            None => false
+        }
+    }
+}
+
+fn registry_name(id: u32) -> String {
+    format!("__RustTimer{}__", id)
+}
+
+/// Event loop providing timer functions to a State
+///
+/// Installs `setTimeout`, `setInterval`, `clearTimeout`,
+/// `clearInterval` and `queueMicrotask` on the global object. The
+/// callbacks and their arguments are kept in the registry until the
+/// timer has fired or is cleared.
+///
+/// Microtasks share the queue of thenable callbacks and are run,
+/// together with pending async host functions, by
+/// State::run_until_idle() before each timer fires.
+///
+/// Constructed with EventLoop::with_virtual_clock() time stands
+/// still unless advanced, and waiting for a timer jumps the clock
+/// forward instead of sleeping, which keeps tests fast and
+/// deterministic.
+///
+/// # Examples
+///
+/// ```
+/// use mujs;
+///
+/// let state = mujs::State::new(mujs::JS_STRICT);
+/// let event_loop = mujs::EventLoop::with_virtual_clock(&state).unwrap();
+///
+/// state.dostring("
+///     var log = [];
+///     setTimeout(function () { log.push('timeout'); }, 1000);
+///     queueMicrotask(function () { log.push('microtask'); });
+/// ").unwrap();
+///
+/// event_loop.run().unwrap();
+///
+/// state.dostring("var result = log.join();").unwrap();
+/// state.getglobal("result").unwrap();
+/// assert_eq!(state.tostring(-1).unwrap(), "microtask,timeout");
+/// ```
+pub struct EventLoop<'a> {
+    state: &'a State,
+    timers: Arc<Mutex<Timers>>,
+}
+
+impl<'a> EventLoop<'a> {
+
+    /// Install timer functions on state using the system clock
+    pub fn new(state: &'a State) -> Result<EventLoop<'a>, String> {
+        EventLoop::create(state, None)
+    }
+
+    /// Install timer functions on state using a virtual clock
+    pub fn with_virtual_clock(state: &'a State) -> Result<EventLoop<'a>, String> {
+        EventLoop::create(state, Some(Instant::now()))
+    }
+
+    fn create(state: &'a State, virtual_now: Option<Instant>) -> Result<EventLoop<'a>, String> {
+        let event_loop = EventLoop {
+            state,
+            timers: Arc::new(Mutex::new(Timers {
+                next_seq: 0,
+                virtual_now,
+                timers: Vec::new(),
+            })),
+        };
+
+        event_loop._install_set("setTimeout", false)?;
+        event_loop._install_set("setInterval", true)?;
+        event_loop._install_clear("clearTimeout")?;
+        event_loop._install_clear("clearInterval")?;
+
+        state.newfunction(|js| {
+            if !js.iscallable(1) {
+                let _ = js.typeerror("queueMicrotask: callback is not a function");
+                return;
+            }
+            js.copy(1);
+            if js._enqueue_job().is_err() {
+                let _ = js.error("queueMicrotask: failed to queue callback");
+            }
+        }, "queueMicrotask", 1)?;
+        state.setglobal("queueMicrotask")?;
+
+        Ok(event_loop)
+    }
+
+    fn _install_set(self: &EventLoop<'a>, name: &'static str, repeat: bool) -> Result<(), String> {
+        let timers = self.timers.clone();
+        self.state.newfunction(move |js| {
+            if !js.iscallable(1) {
+                let _ = js.typeerror(&format!("{}: callback is not a function", name));
+                return;
+            }
+
+            let delay = js.tonumber(2).unwrap_or(0.0);
+            let delay = match delay.is_finite() && delay > 0.0 {
+                true => Duration::from_millis(delay as u64),
+                false => Duration::from_millis(0)
+            };
+
+            // Keep the callback followed by its extra arguments
+            let top = js.gettop();
+            js.newarray();
+            js.copy(1);
+            js.setindex(-2, 0);
+            for i in 3..top {
+                js.copy(i);
+                js.setindex(-2, i - 2);
+            }
+
+            // Ids are unique per State so that event loops sharing it
+            // do not overwrite the registry entries of each other
+            let id = {
+                let next_id = &js._internal().next_timer_id;
+                next_id.set(next_id.get() + 1);
+                next_id.get()
+            };
+
+            if js._setregistry(&registry_name(id)).is_err() {
+                return;
+            }
+
+            {
+                let mut timers = timers.lock().unwrap();
+                timers.next_seq += 1;
+                let timer = Timer {
+                    id,
+                    seq: timers.next_seq,
+                    due: timers.now() + delay,
+                    interval: match repeat {
+                        true => Some(delay),
+                        false => None
+                    },
+                };
+                timers.timers.push(timer);
+            }
+
+            js.pushnumber(id as f64);
+        }, name, 2)?;
+        self.state.setglobal(name)
+    }
+
+    fn _install_clear(self: &EventLoop<'a>, name: &'static str) -> Result<(), String> {
+        let timers = self.timers.clone();
+        self.state.newfunction(move |js| {
+            let id = match js.tonumber(1) {
+                Ok(id) if id >= 1.0 && id <= u32::MAX as f64 => id as u32,
+                _ => return
+            };
+
+            if timers.lock().unwrap().remove(id) {
+                let _ = js._delregistry(&registry_name(id));
+            }
+        }, name, 1)?;
+        self.state.setglobal(name)
+    }
+
+    /// Current time of the event loop clock
+    pub fn now(self: &EventLoop<'a>) -> Instant {
+        self.timers.lock().unwrap().now()
+    }
+
+    /// Move the virtual clock forward without running any timers
+    ///
+    /// Has no effect when using the system clock.
+    pub fn advance(self: &EventLoop<'a>, duration: Duration) {
+        let mut timers = self.timers.lock().unwrap();
+        if let Some(now) = timers.virtual_now {
+            timers.virtual_now = Some(now + duration);
+        }
+    }
+
+    /// Number of timers which have not yet fired or been cleared
+    pub fn pending(self: &EventLoop<'a>) -> usize {
+        self.timers.lock().unwrap().timers.len()
+    }
+
+    /// Fire the timer at index, which must be due
+    fn _fire(self: &EventLoop<'a>, index: usize) -> Result<(), String> {
+        let (name, repeat) = {
+            let mut timers = self.timers.lock().unwrap();
+            let now = timers.now();
+            timers.next_seq += 1;
+            let seq = timers.next_seq;
+            let timer = &mut timers.timers[index];
+            let name = registry_name(timer.id);
+            match timer.interval {
+                Some(interval) => {
+                    timer.due = now + interval;
+                    timer.seq = seq;
+                    (name, true)
+                },
+                None => {
+                    timers.timers.remove(index);
+                    (name, false)
+                }
+            }
+        };
+
+        let state = self.state;
+        state._getregistry(&name)?;
+        if !repeat {
+            state._delregistry(&name)?;
+        }
+
+        let n = state.getlength(-1);
+        state.getindex(-1, 0);
+        state.pushundefined();
+        for i in 1..n {
+            state.getindex(-3 - (i - 1), i);
+        }
+        let res = state.call(n - 1);
+        state.pop(2);
+        res.map_err(String::from)
+    }
+
+    /// Run queued microtasks and the timers which are due
+    ///
+    /// Timers scheduled while running are left for the next
+    /// iteration, even when they are already due. Returns the number
+    /// of timers which fired.
+    pub fn run_once(self: &EventLoop<'a>) -> Result<usize, String> {
+        self.state.run_until_idle()?;
+
+        let (now, seq) = {
+            let timers = self.timers.lock().unwrap();
+            (timers.now(), timers.next_seq)
+        };
+
+        let mut fired = 0;
+        loop {
+            let index = {
+                let timers = self.timers.lock().unwrap();
+                timers.timers.iter()
+                    .enumerate()
+                    .filter(|&(_, timer)| timer.due <= now && timer.seq <= seq)
+                    .min_by_key(|&(_, timer)| (timer.due, timer.seq))
+                    .map(|(i, _)| i)
+            };
+
+            match index {
+                Some(index) => {
+                    self._fire(index)?;
+                    self.state.run_until_idle()?;
+                    fired += 1;
+                },
+                None => return Ok(fired)
+            }
+        }
+    }
+
+    /// Run until no timers remain
+    ///
+    /// Pending async host functions are polled on every iteration
+    /// but not waited for once all timers have fired.
+    pub fn run(self: &EventLoop<'a>) -> Result<(), String> {
+        self._run(None)
+    }
+
+    /// Run until no timers remain or deadline is reached
+    ///
+    /// Timers due after deadline are kept, with the virtual clock
+    /// the clock is moved to deadline.
+    pub fn run_until(self: &EventLoop<'a>, deadline: Instant) -> Result<(), String> {
+        self._run(Some(deadline))
+    }
+
+    fn _run(self: &EventLoop<'a>, deadline: Option<Instant>) -> Result<(), String> {
+        loop {
+            self.run_once()?;
+
+            let mut timers = self.timers.lock().unwrap();
+            let next = match timers.next() {
+                Some(index) => timers.timers[index].due,
+                None => return Ok(())
+            };
+
+            let (wake, done) = match deadline {
+                Some(deadline) if next > deadline => (deadline, true),
+                _ => (next, false)
+            };
+
+            match timers.virtual_now {
+                Some(now) => {
+                    if wake > now {
+                        timers.virtual_now = Some(wake);
+                    }
+                },
+                None => {
+                    drop(timers);
+                    let now = Instant::now();
+                    if wake > now {
+                        thread::sleep(wake - now);
+                    }
+                }
+            }
+
+            if done {
+                return Ok(());
+            }
+        }
+    }
+}
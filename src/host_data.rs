@@ -0,0 +1,105 @@
+//! Typed host data attached to a State
+
+use std::any::{Any, TypeId};
+use std::collections::{HashMap, HashSet};
+
+use State;
+
+/// Values set with State::set_userdata(), one per type
+pub struct HostData {
+    values: HashMap<TypeId, Box<dyn Any + Send>>,
+    /// Replaced values, kept alive since references handed out by
+    /// State::userdata() may still exist
+    replaced: Vec<Box<dyn Any + Send>>,
+    /// Types kept by State::_restore_userdata()
+    snapshot: HashSet<TypeId>,
+}
+
+impl HostData {
+    pub fn new() -> HostData {
+        HostData {
+            values: HashMap::new(),
+            replaced: Vec::new(),
+            snapshot: HashSet::new(),
+        }
+    }
+}
+
+impl State {
+
+    /// Attach a value to the State, replacing any value of the same
+    /// type
+    ///
+    /// The value can be retrieved with State::userdata(), also from
+    /// within functions registered with State::newfunction(), which
+    /// avoids capturing shared host context in each closure. Use
+    /// types with interior mutability such as Cell or RefCell for
+    /// data modified by callbacks.
+    ///
+    /// A replaced value is dropped with the State or by
+    /// State::clear_userdata(), not immediately, as references to it
+    /// may still exist. Replacing values repeatedly on a long lived
+    /// State thus keeps all of them in memory, prefer setting a
+    /// value once and modifying it through interior mutability.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use mujs;
+    /// use std::cell::Cell;
+    ///
+    /// struct Calls(Cell<u32>);
+    ///
+    /// let state = mujs::State::new(mujs::JS_STRICT);
+    /// state.set_userdata(Calls(Cell::new(0)));
+    ///
+    /// state.newfunction(|js| {
+    ///     let calls = js.userdata::<Calls>().unwrap();
+    ///     calls.0.set(calls.0.get() + 1);
+    /// }, "tick", 0).unwrap();
+    /// state.setglobal("tick").unwrap();
+    ///
+    /// state.dostring("tick(); tick();").unwrap();
+    /// assert_eq!(state.userdata::<Calls>().unwrap().0.get(), 2);
+    /// ```
+    pub fn set_userdata<T>(self: &State, value: T) where T: Send + 'static {
+        let mut host_data = self._internal().host_data.borrow_mut();
+        if let Some(old) = host_data.values.insert(TypeId::of::<T>(), Box::new(value)) {
+            host_data.replaced.push(old);
+        }
+    }
+
+    /// Get the value of type T attached with State::set_userdata()
+    pub fn userdata<T>(self: &State) -> Option<&T> where T: Send + 'static {
+        let host_data = self._internal().host_data.borrow();
+        host_data.values.get(&TypeId::of::<T>())
+            .and_then(|value| value.downcast_ref::<T>())
+            // Boxed values are never moved or dropped before the
+            // State, so they outlive the borrow of the map.
+            .map(|value| unsafe { &*(value as *const T) })
+    }
+
+    /// Drop all values attached with State::set_userdata(),
+    /// including replaced values
+    pub fn clear_userdata(self: &mut State) {
+        let mut host_data = self._internal().host_data.borrow_mut();
+        host_data.values.clear();
+        host_data.replaced.clear();
+    }
+
+    /// Remember the types of the values currently attached
+    pub(crate) fn _snapshot_userdata(self: &State) {
+        let mut host_data = self._internal().host_data.borrow_mut();
+        host_data.snapshot = host_data.values.keys().cloned().collect();
+    }
+
+    /// Drop replaced values and values of types attached since
+    /// State::_snapshot_userdata()
+    pub(crate) fn _restore_userdata(self: &mut State) {
+        let mut host_data = self._internal().host_data.borrow_mut();
+        let host_data = &mut *host_data;
+        let snapshot = &host_data.snapshot;
+        host_data.values.retain(|type_id, _| snapshot.contains(type_id));
+        host_data.replaced.clear();
+    }
+}
@@ -0,0 +1,304 @@
+//! Conversion between JSON and values on the stack
+
+use State;
+use JsError;
+
+impl State {
+
+    /// Push the JSON object method name followed by JSON as this
+    fn _json_method(self: &State, name: &str) -> Result<(), String> {
+        self.getglobal("JSON")?;
+        self.getproperty(-1, name)?;
+        self.rot(2);
+        Ok(())
+    }
+
+    /// Parse JSON text and push the resulting value
+    ///
+    /// Uses the native `JSON.parse` in a protected call, on
+    /// malformed input nothing is pushed and the SyntaxError is
+    /// returned.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use mujs;
+    ///
+    /// let state = mujs::State::new(mujs::JS_STRICT);
+    ///
+    /// state.push_json("{\"name\": \"Tester\", \"scores\": [1, 2]}").unwrap();
+    /// state.getproperty(0, "name").unwrap();
+    /// assert_eq!(state.tostring(1).unwrap(), "Tester");
+    ///
+    /// assert!(state.push_json("{name: 1}").is_err());
+    /// ```
+    pub fn push_json(self: &State, text: &str) -> Result<(), JsError> {
+        self._json_method("parse")?;
+        self.pushstring(text);
+        if let Err(err) = self.call(1) {
+            self.pop(1);
+            return Err(JsError::new(err));
+        }
+        Ok(())
+    }
+
+    /// Serialize value at stack index to JSON text
+    ///
+    /// Uses the native `JSON.stringify` in a protected call, nested
+    /// values are indented by indent spaces per level or compact
+    /// when indent is zero. Cyclic structures and values which have
+    /// no JSON representation, such as functions or undefined, are
+    /// returned as errors.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use mujs;
+    ///
+    /// let state = mujs::State::new(mujs::JS_STRICT);
+    ///
+    /// state.dostring("var me = {name: 'Tester', scores: [1, 2]};").unwrap();
+    /// state.getglobal("me").unwrap();
+    /// assert_eq!(state.to_json(0, 0).unwrap(), "{\"name\":\"Tester\",\"scores\":[1,2]}");
+    /// ```
+    pub fn to_json(self: &State, idx: i32, indent: usize) -> Result<String, JsError> {
+        let idx = match idx < 0 {
+            true => self.gettop() + idx,
+            false => idx
+        };
+
+        self._json_method("stringify")?;
+        self.copy(idx);
+        self.pushnull();
+        self.pushnumber(indent as f64);
+        if let Err(err) = self.call(3) {
+            self.pop(1);
+            return Err(JsError::new(err));
+        }
+
+        let res = match self.isundefined(-1) {
+            true => Err(JsError::new("Value has no JSON representation")),
+            false => self.to_str(-1).map_err(JsError::from)
+        };
+        self.pop(1);
+        res
+    }
+}
+
+#[cfg(feature = "serde_json")]
+mod value {
+    use serde_json::{Map, Number, Value};
+
+    use {State, JsError};
+
+    /// Maximum nesting of arrays and objects converted to a Value
+    const MAX_DEPTH: usize = 100;
+
+    /// Registry entry holding the function reading a property
+    static GET_REGISTRY_NAME: &str = "__RustJsonGet__";
+
+    /// Script evaluating to a function returning a named property
+    static GET_SOURCE: &str = "(function (object, name) { return object[name]; })";
+
+    impl State {
+
+        /// Push a serde_json::Value without going through JSON text
+        ///
+        /// # Examples
+        ///
+        /// ```
+        /// extern crate mujs;
+        /// #[macro_use]
+        /// extern crate serde_json;
+        ///
+        /// # fn main() {
+        /// let state = mujs::State::new(mujs::JS_STRICT);
+        ///
+        /// state.push_json_value(&json!({"name": "Tester", "scores": [1, 2]}));
+        /// assert_eq!(state.to_json_value(0).unwrap()["scores"][1], 2);
+        /// # }
+        /// ```
+        pub fn push_json_value(self: &State, value: &Value) {
+            match *value {
+                Value::Null => self.pushnull(),
+                Value::Bool(value) => self.pushboolean(value),
+                Value::Number(ref value) => self.pushnumber(value.as_f64().unwrap_or(0.0)),
+                Value::String(ref value) => self.pushstring(value),
+                Value::Array(ref items) => {
+                    self.newarray();
+                    for (i, item) in items.iter().enumerate() {
+                        self.push_json_value(item);
+                        self.setindex(-2, i as i32);
+                    }
+                },
+                Value::Object(ref map) => {
+                    self.newobject();
+                    for (name, item) in map {
+                        self.push_json_value(item);
+                        // Names with interior nul bytes can not be
+                        // passed to MuJS and are skipped.
+                        if self.setproperty(-2, name).is_err() {
+                            self.pop(1);
+                        }
+                    }
+                }
+            }
+        }
+
+        /// Convert value at stack index into a serde_json::Value
+        /// without going through JSON text
+        ///
+        /// Follows JSON.stringify(), the toJSON() method of objects
+        /// such as Date is called, non-finite numbers become null
+        /// while functions and undefined properties are skipped in
+        /// objects and become null in arrays. Cyclic structures,
+        /// nesting deeper than 100 levels and exceptions thrown by
+        /// toJSON() methods or getters are returned as errors.
+        pub fn to_json_value(self: &State, idx: i32) -> Result<Value, JsError> {
+            let idx = match idx < 0 {
+                true => self.gettop() + idx,
+                false => idx
+            };
+            let mut ancestors = Vec::new();
+            match self._to_json_value(idx, "", &mut ancestors)? {
+                Some(value) => Ok(value),
+                None => Err(JsError::new("Value has no JSON representation"))
+            }
+        }
+
+        /// Push named property of object at stack index
+        ///
+        /// The property is read in a protected call since a getter
+        /// may throw, on error nothing is pushed.
+        fn _json_get(self: &State, idx: i32, name: &str) -> Result<(), JsError> {
+            self._getregistry(GET_REGISTRY_NAME)?;
+            if self.isundefined(-1) {
+                self.pop(1);
+                self.loadstring("[json]", GET_SOURCE)?;
+                self.pushundefined();
+                self.call(0)?;
+                self.copy(-1);
+                self._setregistry(GET_REGISTRY_NAME)?;
+            }
+            self.pushundefined();
+            self.copy(idx);
+            self.pushstring(name);
+            if let Err(err) = self.call(2) {
+                self.pop(1);
+                return Err(err);
+            }
+            Ok(())
+        }
+
+        /// Convert value at stack index, replaced by the result of
+        /// its toJSON() method called with key if it has one
+        fn _to_json_value(self: &State, idx: i32, key: &str,
+                          ancestors: &mut Vec<i32>) -> Result<Option<Value>, JsError> {
+            if !self.isobject(idx) {
+                return self._json_value(idx, ancestors);
+            }
+            self._json_get(idx, "toJSON")?;
+            if !self.iscallable(-1) {
+                self.pop(1);
+                return self._json_value(idx, ancestors);
+            }
+            self.copy(idx);
+            self.pushstring(key);
+            if let Err(err) = self.call(1) {
+                self.pop(1);
+                return Err(err);
+            }
+            let top = self.gettop() - 1;
+            let value = self._json_value(top, ancestors);
+            self.pop(1);
+            value
+        }
+
+        fn _json_value(self: &State, idx: i32,
+                       ancestors: &mut Vec<i32>) -> Result<Option<Value>, JsError> {
+            if self.isundefined(idx) || self.iscallable(idx) {
+                return Ok(None);
+            }
+            if self.isnull(idx) {
+                return Ok(Some(Value::Null));
+            }
+            if self.isboolean(idx) {
+                return Ok(Some(Value::Bool(self.toboolean(idx)?)));
+            }
+            if self.isnumber(idx) {
+                let n = self.tonumber(idx)?;
+                let value = match n.fract() == 0.0 && n.abs() < 9007199254740992.0 {
+                    true => Value::from(n as i64),
+                    false => Number::from_f64(n).map(Value::Number).unwrap_or(Value::Null)
+                };
+                return Ok(Some(value));
+            }
+            if self.isstring(idx) {
+                return Ok(Some(Value::String(self.to_str(idx)?)));
+            }
+
+            for &ancestor in ancestors.iter() {
+                self.copy(ancestor);
+                self.copy(idx);
+                let cyclic = self.strictequal();
+                self.pop(2);
+                if cyclic {
+                    return Err(JsError::new("Converting cyclic structure to JSON"));
+                }
+            }
+            // Each level keeps a value on the stack, stop well before
+            // MuJS runs out of stack space.
+            if ancestors.len() >= MAX_DEPTH {
+                return Err(JsError::new(format!("JSON nesting deeper than {} levels", MAX_DEPTH)));
+            }
+
+            ancestors.push(idx);
+            let res = match self.isarray(idx) {
+                true => self._json_array(idx, ancestors),
+                false => self._json_object(idx, ancestors)
+            };
+            ancestors.pop();
+            res.map(Some)
+        }
+
+        fn _json_array(self: &State, idx: i32,
+                       ancestors: &mut Vec<i32>) -> Result<Value, JsError> {
+            self._json_get(idx, "length")?;
+            let len = self.tointeger(-1);
+            self.pop(1);
+
+            let mut items = Vec::new();
+            for i in 0..len? {
+                let key = i.to_string();
+                self._json_get(idx, &key)?;
+                let top = self.gettop() - 1;
+                let item = self._to_json_value(top, &key, ancestors);
+                self.pop(1);
+                items.push(item?.unwrap_or(Value::Null));
+            }
+            Ok(Value::Array(items))
+        }
+
+        fn _json_object(self: &State, idx: i32,
+                        ancestors: &mut Vec<i32>) -> Result<Value, JsError> {
+            let mut names = Vec::new();
+            self.pushiterator(idx, true);
+            while let Some(name) = self.nextiterator(-1) {
+                names.push(name);
+            }
+            self.pop(1);
+
+            let mut map = Map::new();
+            for name in names {
+                self._json_get(idx, &name)?;
+                let top = self.gettop() - 1;
+                let item = self._to_json_value(top, &name, ancestors);
+                self.pop(1);
+                if let Some(item) = item? {
+                    map.insert(name, item);
+                }
+            }
+            Ok(Value::Object(map))
+        }
+    }
+}
@@ -23,122 +23,178 @@
 #[macro_use]
 extern crate bitflags;
 extern crate libc;
+pub extern crate mujs_sys as sys;
+#[cfg(feature = "log")]
+#[macro_use]
+extern crate log;
+#[cfg(feature = "serde_json")]
+extern crate serde_json;
+
+mod utf;
+mod value;
+mod builder;
+mod worker;
+mod pool;
+mod task;
+mod event_loop;
+mod console;
+mod error;
+mod json;
+mod userdata;
+mod host_data;
+mod scope;
+mod args;
+mod constructor;
+mod dynamic;
+mod view;
+mod checked;
+
+pub use value::{ToJs, FromJs};
+pub use args::{Args, CallError};
+pub use builder::{ObjectBuilder, ArrayBuilder};
+pub use worker::{JsWorker, JobHandle};
+pub use pool::{StatePool, StateGuard, PoolMetrics};
+pub use event_loop::EventLoop;
+pub use error::{JsError, StackFrame};
+pub use console::{ConsoleLevel, ConsoleSink, StdioSink};
+pub use scope::Scope;
+pub use dynamic::DynamicObject;
+pub use view::JsView;
+#[cfg(feature = "log")]
+pub use console::LogSink;
 
-
-#[link(name = "mujs", kind="static")]
 use std::ffi::{CStr, CString};
+use std::borrow::Cow;
+use std::cell::{Cell, RefCell};
+use std::marker::PhantomData;
+use std::mem::ManuallyDrop;
+use std::ops::Deref;
 
+use sys::*;
 
 use libc::{
     c_int,
-    c_double,
     c_void,
     c_char
 };
 
+/// Conversion of a string slice into a temporary C string
+///
+/// The returned CString is owned by the caller and should only be
+/// borrowed with as_ptr() for the duration of the FFI call, this way
+/// the allocation is reclaimed when it goes out of scope.
 trait ToCString {
-    fn to_cstring(self: &Self) -> Result<CString, std::ffi::NulError>;
+    fn to_cstring(&self) -> Result<CString, String>;
 }
 
 impl ToCString for str {
-    fn to_cstring(self: &str) -> Result<CString, std::ffi::NulError> {
+    fn to_cstring(self: &str) -> Result<CString, String> {
         match CString::new(self) {
-            Err(e) => Err(e),
+            Err(e) => Err(format!("Interior nul byte found in string at position {}",
+                                  e.nul_position())),
             Ok(cstr) => Ok(cstr)
         }
     }
 }
 
-extern {
-    fn js_newstate(alloc: *const c_void, context: *const c_void, flags: c_int) -> *const c_void;
-    fn js_freestate(J: *const c_void);
-    fn js_setcontext(J: *const c_void, uctx: *const c_void);
-    fn js_getcontext(J: *const c_void) -> *const c_void;
-    fn js_atpanic(J: *const c_void, panic: Option<extern fn(J: *const c_void)>) -> *const c_void;
-    fn js_gc(J: *const c_void, report: c_int);
-    fn js_ploadstring(J: *const c_void, filename: *const c_char, source: *const c_char) -> c_int;
-    fn js_pcall(J: *const c_void, n: c_int) -> c_int;
-    fn js_pconstruct(J: *const c_void, n: c_int) -> c_int;
-    // fn js_call(J: *const c_void, n: c_int) -> c_int;
-    fn js_dostring(J: *const c_void, source: *const c_char) -> c_int;
-
-    fn js_newobject(J: *const c_void);
-    fn js_newarray(J: *const c_void);
-    fn js_newboolean(J: *const c_void, v: c_int);
-    fn js_newnumber(J: *const c_void, v: c_double);
-    fn js_newstring(J: *const c_void, v: *const c_char);
-    fn js_newregexp(J: *const c_void, pattern: *const c_char, flags: c_int);
-
-    fn js_newuserdata(J: *const c_void, tag: *const c_char, data: *mut c_void,
-                      finalize: Option<extern fn(J: *const c_void, data: *mut c_void)>);
-    fn js_touserdata(J: *const c_void, idx: c_int, tag: *const c_char)-> *mut c_void;
-
-    fn js_isobject(J: *const c_void, idx: c_int) -> c_int;
-    fn js_isarray(J: *const c_void, idx: c_int) -> c_int;
-    fn js_iscallable(J: *const c_void, idx: c_int) -> c_int;
-    fn js_isregexp(J: *const c_void, idx: c_int) -> c_int;
-
-    fn js_hasproperty(J: *const c_void, idx: c_int, name: *const c_char) -> c_int;
-    fn js_getproperty(J: *const c_void, idx: c_int, name: *const c_char);
-    fn js_setproperty(J: *const c_void, idx: c_int, name: *const c_char);
-    fn js_defproperty(J: *const c_void, idx: c_int, name: *const c_char, attrs: c_int);
-    fn js_defaccessor(J: *const c_void, idx: c_int, name: *const c_char, attrs: c_int);
-    fn js_delproperty(J: *const c_void, idx: c_int, name: *const c_char);
-
-    fn js_getlength(J: *const c_void, idx: c_int) -> c_int;
-    fn js_setlength(J: *const c_void, idx: c_int, length: c_int);
-    fn js_hasindex(J: *const c_void, idx: c_int, i: c_int) -> c_int;
-    fn js_getindex(J: *const c_void, idx: c_int, i: c_int);
-    fn js_setindex(J: *const c_void, idx: c_int, i: c_int);
-    fn js_delindex(J: *const c_void, idx: c_int, i: c_int);
-
-    fn js_currentfunction(J: *const c_void);
-    fn js_pushglobal(J: *const c_void);
-    fn js_getglobal(J: *const c_void, name: *const c_char);
-    fn js_setglobal(J: *const c_void, name: *const c_char);
-    fn js_defglobal(J: *const c_void, name: *const c_char, attrs: c_int);
-
-    fn js_newcfunction(J: *const c_void, func: Option<extern fn(J: *const c_void)>,
-                       name: *const c_char,length: c_int);
-
-    fn js_pushundefined(J: *const c_void);
-    fn js_pushnull(J: *const c_void);
-    fn js_pushboolean(J: *const c_void, v: c_int);
-    fn js_pushnumber(J: *const c_void, v: c_double);
-    fn js_pushstring(J: *const c_void, v: *const c_char);
-
-    fn js_isdefined(J: *const c_void, idx: c_int) -> c_int;
-    fn js_isundefined(J: *const c_void, idx: c_int) -> c_int;
-    fn js_isnull(J: *const c_void, idx: c_int) -> c_int;
-    fn js_isboolean(J: *const c_void, idx: c_int) -> c_int;
-    fn js_isnumber(J: *const c_void, idx: c_int) -> c_int;
-    fn js_isstring(J: *const c_void, idx: c_int) -> c_int;
-    fn js_isprimitive(J: *const c_void, idx: c_int) -> c_int;
-
-    fn js_throw(J: *const c_void);
-
-    fn js_newerror(J: *const c_void, message: *const c_char);
-    fn js_newevalerror(J: *const c_void, message: *const c_char);
-    fn js_newrangeerror(J: *const c_void, message: *const c_char);
-    fn js_newreferenceerror(J: *const c_void, message: *const c_char);
-    fn js_newsyntaxerror(J: *const c_void, message: *const c_char);
-    fn js_newtypeerror(J: *const c_void, message: *const c_char);
-    fn js_newurierror(J: *const c_void, message: *const c_char);
-
-    fn js_gettop(J: *const c_void) -> c_int;
-    fn js_pop(J: *const c_void, n: c_int);
-    fn js_copy(J: *const c_void, idx: c_int);
-    fn js_rot(J: *const c_void, n: c_int);
-    fn js_remove(J: *const c_void, idx: c_int);
-
-    fn js_tostring(J: *const c_void, idx: i32) -> *const c_char;
-    fn js_toboolean(J: *const c_void, idx: i32) -> c_int;
-    fn js_tonumber(J: *const c_void, idx: i32) -> c_double;
-
-    fn js_getregistry(J: *const c_void, name: *const c_char);
-    fn js_setregistry(J: *const c_void, name: *const c_char);
-    fn js_delregistry(J: *const c_void, name: *const c_char);
+/// Pre-validated property name
+///
+/// A PropertyKey holds the C representation of a property name so
+/// that it can be passed to the property, global and registry
+/// methods of State without allocating and validating a temporary C
+/// string on each call. This is useful for names used in hot paths.
+///
+/// # Examples
+///
+/// ```
+/// use mujs;
+/// use std::ffi::CStr;
+///
+/// const X: mujs::PropertyKey = mujs::PropertyKey::from_static(
+///     unsafe { CStr::from_bytes_with_nul_unchecked(b"x\0") });
+///
+/// let state = mujs::State::new(mujs::JS_STRICT);
+/// let y = mujs::PropertyKey::new("y").unwrap();
+///
+/// state.newobject();
+/// state.pushnumber(1.0);
+/// state.setproperty(0, &X).unwrap();
+/// state.pushnumber(2.0);
+/// state.setproperty(0, &y).unwrap();
+///
+/// state.getproperty(0, &y).unwrap();
+/// assert_eq!(state.tonumber(1).unwrap(), 2.0);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct PropertyKey {
+    name: Cow<'static, CStr>,
+}
+
+impl PropertyKey {
+
+    /// Constructs a new PropertyKey
+    ///
+    /// Returns an error if name contains a nul byte.
+    pub fn new(name: &str) -> Result<PropertyKey, String> {
+        Ok(PropertyKey {
+            name: Cow::Owned(name.to_cstring()?)
+        })
+    }
+
+    /// Constructs a PropertyKey borrowing a static C string
+    pub const fn from_static(name: &'static CStr) -> PropertyKey {
+        PropertyKey {
+            name: Cow::Borrowed(name)
+        }
+    }
+
+    /// Get property name as a C string
+    pub fn as_cstr(self: &PropertyKey) -> &CStr {
+        &self.name
+    }
+}
+
+/// Types which can be used as property names
+///
+/// Implemented for string slices, which are validated and converted
+/// on each use, and for PropertyKey and CStr which are passed as is.
+pub trait AsPropertyKey {
+    /// Call function with name as a nul terminated C string
+    fn with_key<R, F>(&self, func: F) -> Result<R, String>
+        where F: FnOnce(*const c_char) -> R;
+}
+
+impl AsPropertyKey for str {
+    fn with_key<R, F>(self: &str, func: F) -> Result<R, String>
+        where F: FnOnce(*const c_char) -> R
+    {
+        let name = self.to_cstring()?;
+        Ok(func(name.as_ptr()))
+    }
+}
+
+impl AsPropertyKey for String {
+    fn with_key<R, F>(self: &String, func: F) -> Result<R, String>
+        where F: FnOnce(*const c_char) -> R
+    {
+        self.as_str().with_key(func)
+    }
+}
+
+impl AsPropertyKey for CStr {
+    fn with_key<R, F>(self: &CStr, func: F) -> Result<R, String>
+        where F: FnOnce(*const c_char) -> R
+    {
+        Ok(func(self.as_ptr()))
+    }
+}
 
+impl AsPropertyKey for PropertyKey {
+    fn with_key<R, F>(self: &PropertyKey, func: F) -> Result<R, String>
+        where F: FnOnce(*const c_char) -> R
+    {
+        Ok(func(self.name.as_ptr()))
+    }
 }
 
 bitflags! {
@@ -154,8 +210,8 @@ bitflags! {
         ///
         /// state.newobject();
         /// state.pushnumber(32.0);
-        /// state.setproperty(-2, "age");
-        /// state.defglobal("me", mujs::JS_READONLY);
+        /// state.setproperty(-2, "age").unwrap();
+        /// state.defglobal("me", mujs::JS_READONLY).unwrap();
         /// ```
         ///
         /// The above example defines an object in global space named
@@ -193,19 +249,118 @@ bitflags! {
     }
 }
 
+/// Position of an item on the stack
+///
+/// The raw stack methods of State take an `i32` where non-negative
+/// values count from the bottom of the stack and negative values
+/// count from the top, and silently yield undefined for positions
+/// outside of the stack. StackIndex makes the direction explicit
+/// and is validated against State::gettop() by methods taking it.
+///
+/// The raw methods keep taking an unchecked `i32`. Each raw method
+/// taking a stack index has a counterpart with an `_at` suffix
+/// taking a StackIndex, such as State::getproperty_at() or
+/// State::copy_at(), which returns an error for positions outside
+/// of the stack. Those reading or writing properties also fail for
+/// undefined and null, which MuJS would raise a TypeError for, and
+/// those popping values fail if there are not enough items above
+/// the object. The array helpers such as State::array_get() take a
+/// StackIndex as well.
+///
+/// # Examples
+///
+/// ```
+/// use mujs;
+/// use mujs::StackIndex::Top;
+///
+/// let state = mujs::State::new(mujs::JS_STRICT);
+/// state.newobject();
+///
+/// state.pushnumber(1.0);
+/// state.setproperty_at(Top(2), "one").unwrap();
+///
+/// assert!(state.getproperty_at(Top(2), "one").is_err());
+/// assert!(state.setproperty_at(Top(1), "two").is_err());
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StackIndex {
+    /// Position counted from bottom of stack, `Absolute(0)` is the
+    /// first item
+    Absolute(usize),
+
+    /// Position counted from top of stack, `Top(1)` is the item on
+    /// top of stack
+    Top(usize),
+}
+
+/// Function receiving warnings and errors reported by MuJS
+type ReportFn = Box<dyn FnMut(&str) + Send>;
+
 struct InternalState {
-    state: *const c_void,
-    memctx: *const c_void,
+    state: *mut js_State,
+    tasks: RefCell<task::Tasks>,
+    report: RefCell<Option<ReportFn>>,
+    host_data: RefCell<host_data::HostData>,
+    /// Last id handed out to a timer of an EventLoop
+    next_timer_id: Cell<u32>,
+    /// Created with JS_STRICT, unknown for a borrowed MuJS state
+    strict: Cell<bool>,
+    /// TypeError thrown once a userdata property hook has returned
+    hook_error: RefCell<Option<String>>,
+    /// Userdata property hook waiting to be run in a protected call
+    hook_call: Cell<*mut userdata::HookCall>,
+    /// Freed by the owning State when dropped rather than by the
+    /// finalizer of its registry entry
+    owned: Cell<bool>,
 }
 
 /// Interpreter state contains the value stack, protected environments
 /// and environment records.
+///
+/// # Thread safety
+///
+/// A State is `Send` but not `Sync`. MuJS keeps no thread local data
+/// for a state so it may be moved to and used from another thread,
+/// but it must never be used from more than one thread at a time.
+/// To uphold this all host data owned by the state, such as the
+/// closures registered with State::newfunction(), are required to be
+/// `Send`. See JsWorker for running a State on a dedicated thread.
 pub struct State {
-    internal: Box<InternalState>,
     ptr: *mut InternalState,
 }
 
-static CLOSURE_TAG: &'static str = "__RustClosure__";
+unsafe impl Send for State {}
+
+/// State borrowed from a MuJS state created and freed elsewhere
+///
+/// Dereferences to State providing the full API but does not free
+/// the MuJS state when dropped. See State::from_raw().
+pub struct StateRef {
+    state: ManuallyDrop<State>,
+    _not_send: PhantomData<*mut ()>,
+}
+
+impl Deref for StateRef {
+    type Target = State;
+
+    fn deref(self: &StateRef) -> &State {
+        &self.state
+    }
+}
+
+static CLOSURE_TAG: &[u8] = b"__RustClosure__\0";
+static CONSTRUCTOR_TAG: &[u8] = b"__RustConstructor__\0";
+static INTERNAL_TAG: &[u8] = b"__RustState__\0";
+
+/// Rust closure of a function object together with the host data of
+/// the State, which lives as long as the MuJS state
+struct Closure {
+    internal: *mut InternalState,
+    func: Box<dyn FnMut(&State)>,
+}
+
+/// Prefix of registry names reserved for entries of this crate
+static RESERVED_REGISTRY_PREFIX: &[u8] = b"__Rust";
 
 impl State {
 
@@ -219,26 +374,159 @@ impl State {
     /// ```
     pub fn new(flags: StateFlags) -> State {
 
-        let mut state = State {
-            internal: Box::new(InternalState{
-                state: std::ptr::null(),
-                memctx: std::ptr::null(),
-            }),
-            ptr: std::ptr::null_mut(),
+        unsafe {
+            let js = js_newstate(None, std::ptr::null_mut(), flags.bits);
+            js_atpanic(js, Some(State::_panic));
+            let ptr = State::_register_internal(js, true);
+            (*ptr).strict.set(flags.contains(JS_STRICT));
+            State {
+                ptr,
+            }
+        }
+    }
+
+    /// Borrow a MuJS state created by C code
+    ///
+    /// Host data of the returned StateRef, such as closures
+    /// registered with State::newfunction(), is kept in the MuJS
+    /// state and freed along with it by js_freestate(). Borrowing
+    /// the same MuJS state again gives access to the same host data.
+    ///
+    /// # Safety
+    ///
+    /// The pointer must be a valid MuJS state which outlives the
+    /// returned StateRef, and must not be used by other code while
+    /// the StateRef is in use.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use mujs;
+    ///
+    /// let raw = mujs::State::new(mujs::JS_STRICT).into_raw();
+    ///
+    /// let state = unsafe { mujs::State::from_raw(raw) };
+    /// state.newfunction(|js| js.pushnumber(42.0), "answer", 0).unwrap();
+    /// state.setglobal("answer").unwrap();
+    ///
+    /// let state = unsafe { mujs::State::from_raw(raw) };
+    /// state.dostring("var result = answer();").unwrap();
+    /// state.getglobal("result").unwrap();
+    /// assert_eq!(state.tonumber(-1).unwrap(), 42.0);
+    ///
+    /// unsafe { mujs::sys::js_freestate(raw) };
+    /// ```
+    pub unsafe fn from_raw(js: *mut js_State) -> StateRef {
+        let mut ptr = State::_lookup_internal(js);
+        if ptr.is_null() {
+            ptr = State::_register_internal(js, false);
+        }
+        StateRef {
+            state: ManuallyDrop::new(State { ptr }),
+            _not_send: PhantomData,
+        }
+    }
+
+    /// Give up ownership of the MuJS state
+    ///
+    /// The state must be freed using js_freestate(), which also
+    /// frees the host data of the State. Use State::from_raw() to
+    /// use it again from Rust.
+    pub fn into_raw(self: State) -> *mut js_State {
+        let internal = self._internal();
+        internal.owned.set(false);
+        let js = internal.state;
+        std::mem::forget(self);
+        js
+    }
+
+    /// Allocate the host data of a MuJS state and store it in the
+    /// registry
+    unsafe fn _register_internal(js: *mut js_State, owned: bool) -> *mut InternalState {
+        let ptr = Box::into_raw(Box::new(InternalState {
+            state: js,
+            tasks: RefCell::new(task::Tasks::new()),
+            report: RefCell::new(None),
+            host_data: RefCell::new(host_data::HostData::new()),
+            next_timer_id: Cell::new(0),
+            strict: Cell::new(false),
+            hook_error: RefCell::new(None),
+            hook_call: Cell::new(std::ptr::null_mut()),
+            owned: Cell::new(owned),
+        }));
+        js_pushnull(js);
+        js_newuserdata(js, INTERNAL_TAG.as_ptr() as *const c_char, ptr as *mut c_void,
+                       Some(State::_finalize_internal));
+        js_setregistry(js, INTERNAL_TAG.as_ptr() as *const c_char);
+        ptr
+    }
+
+    /// Find the host data of a MuJS state, null if not registered
+    unsafe fn _lookup_internal(js: *mut js_State) -> *mut InternalState {
+        js_getregistry(js, INTERNAL_TAG.as_ptr() as *const c_char);
+        let ptr = match js_isuserdata(js, -1, INTERNAL_TAG.as_ptr() as *const c_char) {
+            0 => std::ptr::null_mut(),
+            _ => js_touserdata(js, -1, INTERNAL_TAG.as_ptr() as *const c_char) as *mut InternalState
         };
+        js_pop(js, 1);
+        ptr
+    }
 
-        state.ptr = state.internal.as_mut() as *mut _;
+    extern "C" fn _finalize_internal(_: *mut js_State, data: *mut c_void) {
+        let ptr = data as *mut InternalState;
         unsafe {
-            (*state.ptr).memctx  = state.ptr as *const c_void;
-            (*state.ptr).state = js_newstate(std::ptr::null(), (*state.ptr).memctx, flags.bits);
-            js_setcontext((*state.ptr).state, (*state.ptr).memctx);
-            js_atpanic((*state.ptr).state, Some(State::_panic));
-        };
+            if !(*ptr).owned.get() {
+                drop(Box::from_raw(ptr));
+            }
+        }
+    }
+
+    /// Get the raw MuJS state for use with the functions of the
+    /// mujs-sys crate
+    ///
+    /// The pointer is valid for the lifetime of the State.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use mujs;
+    ///
+    /// let state = mujs::State::new(mujs::JS_STRICT);
+    /// state.pushnumber(1.0);
+    ///
+    /// assert_eq!(unsafe { mujs::sys::js_gettop(state.as_raw()) }, 1);
+    /// ```
+    pub fn as_raw(self: &State) -> *mut js_State {
+        unsafe { (*self.ptr).state }
+    }
+
+    /// Borrow the State of a MuJS state, used by callbacks invoked
+    /// from MuJS
+    ///
+    /// The returned State does not free the MuJS state when dropped.
+    ///
+    /// Throws an error into the script if the host data is missing.
+    unsafe fn _borrowed(js: *mut js_State) -> ManuallyDrop<State> {
+        let ptr = State::_lookup_internal(js);
+        if ptr.is_null() {
+            js_error(js, b"Host data of the state is missing\0".as_ptr() as *const c_char);
+        }
+        State::_from_internal(ptr)
+    }
+
+    /// Borrow the State owning host data, used by callbacks which
+    /// keep a pointer to it to avoid looking it up on each call
+    unsafe fn _from_internal(ptr: *mut InternalState) -> ManuallyDrop<State> {
+        ManuallyDrop::new(State {
+            ptr,
+        })
+    }
 
-        state
+    fn _internal(self: &State) -> &InternalState {
+        unsafe { &*self.ptr }
     }
 
-    extern fn _panic(js: *const c_void) {
+    extern "C" fn _panic(js: *mut js_State) {
         let top = unsafe { js_gettop(js) };
         let res_c_str = unsafe { js_tostring(js, top - 1) };
         let err = unsafe { CStr::from_ptr(res_c_str).to_string_lossy().into_owned() };
@@ -299,21 +587,50 @@ impl State {
     /// println!("{:?}", state.tostring(0).unwrap());
     /// ```
     ///
-    pub fn loadstring(self: &State, filename: &str, source: &str) -> Result<(), String> {
+    pub fn loadstring(self: &State, filename: &str, source: &str) -> Result<(), JsError> {
+        let filename = filename.to_cstring()?;
+        let source = source.to_cstring()?;
         match unsafe {
-            js_ploadstring((*self.ptr).state,
-                           filename.to_cstring().unwrap().into_raw(),
-                           source.to_cstring().unwrap().into_raw())
+            js_ploadstring((*self.ptr).state, filename.as_ptr(), source.as_ptr())
         } {
             0 => Ok(()),
-            _ => {
-                let err = self.tostring(-1);
-                assert!(err.is_ok());
-                Err(err.ok().unwrap())
-            }
+            _ => Err(self._error_at_top())
         }
     }
 
+    /// Check the syntax of a script without executing it
+    ///
+    /// The script is compiled and the resulting function discarded,
+    /// leaving the stack unchanged. On failure the returned error
+    /// carries the file and line of the syntax error.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use mujs;
+    ///
+    /// let state = mujs::State::new(mujs::JS_STRICT);
+    ///
+    /// assert!(state.check_syntax("good.js", "var a = 1;").is_ok());
+    ///
+    /// let err = state.check_syntax("bad.js", "var a = 1;\nvar = 2;").unwrap_err();
+    /// assert_eq!(err.file.unwrap(), "bad.js");
+    /// assert_eq!(err.line, Some(2));
+    /// assert_eq!(state.gettop(), 0);
+    /// ```
+    pub fn check_syntax(self: &State, filename: &str, source: &str) -> Result<(), JsError> {
+        let filename = filename.to_cstring()?;
+        let source = source.to_cstring()?;
+        let res = match unsafe {
+            js_ploadstring((*self.ptr).state, filename.as_ptr(), source.as_ptr())
+        } {
+            0 => Ok(()),
+            _ => Err(self._error_at_top())
+        };
+        self.pop(1);
+        res
+    }
+
     /// Call a function pushed on stack
     ///
     /// Pop the function, this value and all arguments then executes
@@ -345,14 +662,10 @@ impl State {
     ///
     /// ```
     ///
-    pub fn call(self: &State, n: i32) -> Result<(), String> {
+    pub fn call(self: &State, n: i32) -> Result<(), JsError> {
         match unsafe { js_pcall((*self.ptr).state, n) } {
             0 => Ok(()),
-            _ => {
-                let err = self.tostring(-1);
-                assert!(err.is_ok());
-                Err(err.ok().unwrap())
-            }
+            _ => Err(self._error_at_top())
         }
     }
 
@@ -379,29 +692,26 @@ impl State {
     ///                                this.year = year; \
     ///                              }").unwrap();
     ///
-    /// state.getglobal("Car");
+    /// state.getglobal("Car").unwrap();
     /// state.pushstring("Volvo");
     /// state.pushstring("V50");
     /// state.pushnumber(2010.0);
     /// assert!(state.construct(3).is_ok());
     ///
-    /// state.getproperty(0, "model");
+    /// state.getproperty(0, "model").unwrap();
     /// println!("Model: {:?}", state.tostring(1).unwrap());
     ///
     /// ```
-    pub fn construct(self: &State, n: i32) -> Result<(), String> {
+    pub fn construct(self: &State, n: i32) -> Result<(), JsError> {
         match unsafe { js_pconstruct((*self.ptr).state, n) } {
             0 => Ok(()),
-            _ => {
-                let err = self.tostring(-1);
-                assert!(err.is_ok());
-                Err(err.ok().unwrap())
-            }
+            _ => Err(self._error_at_top())
         }
     }
 
     pub fn dostring(self: &State, source: &str) -> Result<(), String> {
-        match unsafe {js_dostring((*self.ptr).state, source.to_cstring().unwrap().into_raw()) } {
+        let source = source.to_cstring()?;
+        match unsafe {js_dostring((*self.ptr).state, source.as_ptr()) } {
             0 => Ok(()),
             _ => {
                 let err = self.tostring(-1);
@@ -423,7 +733,7 @@ impl State {
     ///
     /// let state = mujs::State::new(mujs::JS_STRICT);
     ///
-    /// state.newerror("Lets create an error");
+    /// state.newerror("Lets create an error").unwrap();
     /// state.throw();
     /// ```
     pub fn throw(self: &State) {
@@ -431,94 +741,101 @@ impl State {
     }
 
     ///  Push a Error onto the stack
-    pub fn newerror(self: &State, message: &str) {
-        unsafe { js_newerror((*self.ptr).state, message.to_cstring().unwrap().into_raw()) };
+    pub fn newerror(self: &State, message: &str) -> Result<(), String> {
+        let message = message.to_cstring()?;
+        unsafe { js_newerror((*self.ptr).state, message.as_ptr()) };
+        Ok(())
     }
 
     /// Push an EvaluationError onto the stack
-    pub fn newevalerror(self: &State, message: &str) {
-        unsafe { js_newevalerror((*self.ptr).state, message.to_cstring().unwrap().into_raw()) }
+    pub fn newevalerror(self: &State, message: &str) -> Result<(), String> {
+        let message = message.to_cstring()?;
+        unsafe { js_newevalerror((*self.ptr).state, message.as_ptr()) };
+        Ok(())
     }
 
     /// Push a RangeError onto the stack
-    pub fn newrangeerror(self: &State, message: &str) {
-        unsafe { js_newrangeerror((*self.ptr).state, message.to_cstring().unwrap().into_raw()) }
+    pub fn newrangeerror(self: &State, message: &str) -> Result<(), String> {
+        let message = message.to_cstring()?;
+        unsafe { js_newrangeerror((*self.ptr).state, message.as_ptr()) };
+        Ok(())
     }
 
     /// Push a ReferenceError onto the stack
-    pub fn newreferenceerror(self: &State, message: &str) {
-        unsafe { js_newreferenceerror((*self.ptr).state, message.to_cstring().unwrap().into_raw()) }
+    pub fn newreferenceerror(self: &State, message: &str) -> Result<(), String> {
+        let message = message.to_cstring()?;
+        unsafe { js_newreferenceerror((*self.ptr).state, message.as_ptr()) };
+        Ok(())
     }
 
     /// Push a SyntaxError onto the stack
-    pub fn newsyntaxerror(self: &State, message: &str) {
-        unsafe { js_newsyntaxerror((*self.ptr).state, message.to_cstring().unwrap().into_raw()) }
+    pub fn newsyntaxerror(self: &State, message: &str) -> Result<(), String> {
+        let message = message.to_cstring()?;
+        unsafe { js_newsyntaxerror((*self.ptr).state, message.as_ptr()) };
+        Ok(())
     }
 
     /// Push a TypeError onto the stack
-    pub fn newtypeerror(self: &State, message: &str) {
-        unsafe { js_newtypeerror((*self.ptr).state, message.to_cstring().unwrap().into_raw()) }
+    pub fn newtypeerror(self: &State, message: &str) -> Result<(), String> {
+        let message = message.to_cstring()?;
+        unsafe { js_newtypeerror((*self.ptr).state, message.as_ptr()) };
+        Ok(())
     }
 
     /// Push a URIError onto the stack
-    pub fn newurierror(self: &State, message: &str) {
-        unsafe { js_newurierror((*self.ptr).state, message.to_cstring().unwrap().into_raw()) }
+    pub fn newurierror(self: &State, message: &str) -> Result<(), String> {
+        let message = message.to_cstring()?;
+        unsafe { js_newurierror((*self.ptr).state, message.as_ptr()) };
+        Ok(())
     }
 
     /// Throws an Error in the executing environment
-    pub fn error(self: &State, message: &str) {
-        unsafe {
-            js_newerror((*self.ptr).state, message.to_cstring().unwrap().into_raw());
-            js_throw((*self.ptr).state);
-        };
+    pub fn error(self: &State, message: &str) -> Result<(), String> {
+        self.newerror(message)?;
+        self.throw();
+        Ok(())
     }
 
     /// Throws an EvalError in the executing environment
-    pub fn evalerror(self: &State, message: &str) {
-        unsafe {
-            js_newevalerror((*self.ptr).state, message.to_cstring().unwrap().into_raw());
-            js_throw((*self.ptr).state);
-        }
+    pub fn evalerror(self: &State, message: &str) -> Result<(), String> {
+        self.newevalerror(message)?;
+        self.throw();
+        Ok(())
     }
 
     /// Throws an RangeError in the executing environment
-    pub fn rangeerror(self: &State, message: &str) {
-        unsafe {
-            js_newrangeerror((*self.ptr).state, message.to_cstring().unwrap().into_raw());
-            js_throw((*self.ptr).state);
-        }
+    pub fn rangeerror(self: &State, message: &str) -> Result<(), String> {
+        self.newrangeerror(message)?;
+        self.throw();
+        Ok(())
     }
 
     /// Throws an ReferenceError in the executing environment
-    pub fn referenceerror(self: &State, message: &str) {
-        unsafe {
-            js_newreferenceerror((*self.ptr).state, message.to_cstring().unwrap().into_raw());
-            js_throw((*self.ptr).state);
-        }
+    pub fn referenceerror(self: &State, message: &str) -> Result<(), String> {
+        self.newreferenceerror(message)?;
+        self.throw();
+        Ok(())
     }
 
     /// Throws an SyntaxError in the executing environment
-    pub fn syntaxerror(self: &State, message: &str) {
-        unsafe {
-            js_newsyntaxerror((*self.ptr).state, message.to_cstring().unwrap().into_raw());
-            js_throw((*self.ptr).state);
-        }
+    pub fn syntaxerror(self: &State, message: &str) -> Result<(), String> {
+        self.newsyntaxerror(message)?;
+        self.throw();
+        Ok(())
     }
 
     /// Throws an TypeError in the executing environment
-    pub fn typeerror(self: &State, message: &str) {
-        unsafe {
-            js_newtypeerror((*self.ptr).state, message.to_cstring().unwrap().into_raw());
-            js_throw((*self.ptr).state);
-        }
+    pub fn typeerror(self: &State, message: &str) -> Result<(), String> {
+        self.newtypeerror(message)?;
+        self.throw();
+        Ok(())
     }
 
     /// Throws an URIError in the executing environment
-    pub fn urierror(self: &State, message: &str) {
-        unsafe {
-            js_newurierror((*self.ptr).state, message.to_cstring().unwrap().into_raw());
-            js_throw((*self.ptr).state);
-        }
+    pub fn urierror(self: &State, message: &str) -> Result<(), String> {
+        self.newurierror(message)?;
+        self.throw();
+        Ok(())
     }
 
     /// Get top index of stack
@@ -598,7 +915,7 @@ impl State {
     /// state.setindex(-2, 1);
     ///
     /// state.setlength(-1, 2);
-    /// state.setglobal("arr");
+    /// state.setglobal("arr").unwrap();
     ///
     /// assert!(state.loadstring("script", "               \
     ///                           arr[2] = 'Third item';   \
@@ -628,8 +945,10 @@ impl State {
     }
 
     /// Create a new string and push on top of stack
-    pub fn newstring(self: &State, value: &str) {
-        unsafe { js_newstring((*self.ptr).state, value.to_cstring().unwrap().into_raw()) };
+    pub fn newstring(self: &State, value: &str) -> Result<(), String> {
+        let value = value.to_cstring()?;
+        unsafe { js_newstring((*self.ptr).state, value.as_ptr()) };
+        Ok(())
     }
 
     /// Create a new regular expression and push on top of stack
@@ -646,16 +965,18 @@ impl State {
     ///
     /// let state = mujs::State::new(mujs::JS_STRICT);
     ///
-    /// state.newregexp("^Hello (.*)!$", mujs::JS_REGEXP_G);
-    /// state.getproperty(0, "test");
+    /// state.newregexp("^Hello (.*)!$", mujs::JS_REGEXP_G).unwrap();
+    /// state.getproperty(0, "test").unwrap();
     /// state.copy(0);
     /// state.pushstring("Hello World!");
     /// state.call(1).unwrap();
     ///
     /// assert_eq!(state.toboolean(1).unwrap(), true);
     /// ```
-    pub fn newregexp(self: &State, pattern: &str, flags: RegExpFlags) {
-        unsafe { js_newregexp((*self.ptr).state, pattern.to_cstring().unwrap().into_raw(), flags.bits) };
+    pub fn newregexp(self: &State, pattern: &str, flags: RegExpFlags) -> Result<(), String> {
+        let pattern = pattern.to_cstring()?;
+        unsafe { js_newregexp((*self.ptr).state, pattern.as_ptr(), flags.bits) };
+        Ok(())
     }
 
     /// Test if stack item is an object
@@ -714,8 +1035,39 @@ impl State {
     }
 
     /// Push string primitive value onto the stack
+    ///
+    /// The string may contain nul characters, see
+    /// State::to_str() on how strings are represented within MuJS.
     pub fn pushstring(self: &State, value: &str) {
-        unsafe { js_pushstring((*self.ptr).state, value.to_cstring().unwrap().into_raw()) }
+        let buf = utf::encode_str(value);
+        unsafe { js_pushlstring((*self.ptr).state, buf.as_ptr() as *const c_char, buf.len() as c_int) }
+    }
+
+    /// Push bytes as a string primitive value onto the stack
+    ///
+    /// Each byte is stored as one character with the same code,
+    /// which means that `charCodeAt()` in script returns the byte
+    /// value and the string can be converted back using
+    /// State::to_bytes() without loss.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use mujs;
+    ///
+    /// let state = mujs::State::new(mujs::JS_STRICT);
+    ///
+    /// state.push_bytes(&[0x00, 0x7f, 0xff]);
+    /// state.setglobal("frame").unwrap();
+    ///
+    /// state.dostring("frame = frame + String.fromCharCode(0x80);").unwrap();
+    ///
+    /// state.getglobal("frame").unwrap();
+    /// assert_eq!(state.to_bytes(0).unwrap(), vec![0x00, 0x7f, 0xff, 0x80]);
+    /// ```
+    pub fn push_bytes(self: &State, value: &[u8]) {
+        let buf = utf::encode_bytes(value);
+        unsafe { js_pushlstring((*self.ptr).state, buf.as_ptr() as *const c_char, buf.len() as c_int) }
     }
 
     /// Test if object on stack has named property
@@ -728,27 +1080,30 @@ impl State {
     /// let state = mujs::State::new(mujs::JS_STRICT);
     /// state.newobject();
     /// state.pushnumber(1.234);
-    /// state.setproperty(0, "value");
+    /// state.setproperty(0, "value").unwrap();
     ///
-    /// if state.hasproperty(0, "value") {
+    /// if state.hasproperty(0, "value").unwrap() {
     ///   println!("Value: {:?}", state.tostring(1).unwrap());
     /// }
     ///
-    pub fn hasproperty(self: &State, idx: i32, name: &str) -> bool {
-        match unsafe { js_hasproperty((*self.ptr).state, idx, name.to_cstring().unwrap().into_raw()) } {
-            0 => false,
-            _ => true
-        }
+    pub fn hasproperty<K>(self: &State, idx: i32, name: &K) -> Result<bool, String>
+        where K: AsPropertyKey + ?Sized
+    {
+        name.with_key(|name| unsafe { js_hasproperty((*self.ptr).state, idx, name) } != 0)
     }
 
     /// Pop the value on top of stack and assigns it to named property
-    pub fn setproperty(self: &State, idx: i32, name: &str) {
-        unsafe { js_setproperty((*self.ptr).state, idx, name.to_cstring().unwrap().into_raw()) };
+    pub fn setproperty<K>(self: &State, idx: i32, name: &K) -> Result<(), String>
+        where K: AsPropertyKey + ?Sized
+    {
+        name.with_key(|name| unsafe { js_setproperty((*self.ptr).state, idx, name) })
     }
 
     /// Push the value of named property of object on top of stack
-    pub fn getproperty(self: &State, idx: i32, name: &str) {
-        unsafe { js_getproperty((*self.ptr).state, idx, name.to_cstring().unwrap().into_raw()) };
+    pub fn getproperty<K>(self: &State, idx: i32, name: &K) -> Result<(), String>
+        where K: AsPropertyKey + ?Sized
+    {
+        name.with_key(|name| unsafe { js_getproperty((*self.ptr).state, idx, name) })
     }
 
     /// Define named property of object
@@ -762,11 +1117,13 @@ impl State {
     ///
     /// state.newobject();
     /// state.pushstring("A value");
-    /// state.defproperty(0, "value", mujs::JS_DONTCONF);
+    /// state.defproperty(0, "value", mujs::JS_DONTCONF).unwrap();
     ///
     /// ```
-    pub fn defproperty(self: &State, idx: i32, name: &str, attrs: PropertyAttributes) {
-        unsafe { js_defproperty((*self.ptr).state, idx, name.to_cstring().unwrap().into_raw(), attrs.bits) };
+    pub fn defproperty<K>(self: &State, idx: i32, name: &K, attrs: PropertyAttributes) -> Result<(), String>
+        where K: AsPropertyKey + ?Sized
+    {
+        name.with_key(|name| unsafe { js_defproperty((*self.ptr).state, idx, name, attrs.bits) })
     }
 
     /// Define a getter and setter attribute og a property of object on stack
@@ -784,19 +1141,23 @@ impl State {
     ///
     /// state.newobject();
     ///
-    /// state.newfunction( move |x| { x.pushnumber(25.0) }, "age.getter", 0);
+    /// state.newfunction( move |x| { x.pushnumber(25.0) }, "age.getter", 0).unwrap();
     /// state.pushnull();
     ///
-    /// state.defaccessor(0, "age", mujs::JS_DONTENUM);
+    /// state.defaccessor(0, "age", mujs::JS_DONTENUM).unwrap();
     ///
     /// ```
-    pub fn defaccessor(self: &State, idx: i32, name: &str, attrs: PropertyAttributes) {
-        unsafe { js_defaccessor((*self.ptr).state, idx, name.to_cstring().unwrap().into_raw(), attrs.bits) };
+    pub fn defaccessor<K>(self: &State, idx: i32, name: &K, attrs: PropertyAttributes) -> Result<(), String>
+        where K: AsPropertyKey + ?Sized
+    {
+        name.with_key(|name| unsafe { js_defaccessor((*self.ptr).state, idx, name, attrs.bits) })
     }
 
     /// Delete named property of object
-    pub fn delproperty(self: &State, idx: i32, name: &str) {
-        unsafe { js_delproperty((*self.ptr).state, idx, name.to_cstring().unwrap().into_raw()) };
+    pub fn delproperty<K>(self: &State, idx: i32, name: &K) -> Result<(), String>
+        where K: AsPropertyKey + ?Sized
+    {
+        name.with_key(|name| unsafe { js_delproperty((*self.ptr).state, idx, name) })
     }
 
     /// Get length of an array
@@ -847,46 +1208,178 @@ impl State {
         unsafe { js_delindex((*self.ptr).state, idx, i) }
     }
 
+    /// Validate a stack index and convert it to an absolute index
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use mujs;
+    /// use mujs::StackIndex::{Absolute, Top};
+    ///
+    /// let state = mujs::State::new(mujs::JS_STRICT);
+    /// state.pushnumber(1.0);
+    /// state.pushnumber(2.0);
+    ///
+    /// assert_eq!(state.abs_index(Top(1)).unwrap(), 1);
+    /// assert_eq!(state.abs_index(Absolute(0)).unwrap(), 0);
+    /// assert!(state.abs_index(Top(3)).is_err());
+    /// assert!(state.abs_index(Absolute(2)).is_err());
+    /// ```
+    pub fn abs_index(self: &State, idx: StackIndex) -> Result<i32, String> {
+        let top = self.gettop() as usize;
+        match idx {
+            StackIndex::Absolute(i) if i < top => Ok(i as i32),
+            StackIndex::Top(i) if i >= 1 && i <= top => Ok((top - i) as i32),
+            _ => Err(format!("Stack index {:?} out of range for stack of {} items", idx, top))
+        }
+    }
+
+    fn _array_index(self: &State, idx: StackIndex) -> Result<i32, String> {
+        let idx = self.abs_index(idx)?;
+        match self.isarray(idx) {
+            true => Ok(idx),
+            false => Err(format!("Value at stack index {} is not an array", idx))
+        }
+    }
+
+    /// Get length of array at stack index
+    pub fn array_len(self: &State, idx: StackIndex) -> Result<usize, String> {
+        let idx = self._array_index(idx)?;
+        Ok(self.getlength(idx) as usize)
+    }
+
+    /// Push item at array index onto the stack
+    ///
+    /// Fails if the stack index does not refer to an array or if the
+    /// array index is not less than the length of the array.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use mujs;
+    /// use mujs::StackIndex::Top;
+    ///
+    /// let state = mujs::State::new(mujs::JS_STRICT);
+    ///
+    /// assert!(state.loadstring("script", "[1, 2, 3]").is_ok());
+    /// state.pushundefined();
+    /// assert!(state.call(0).is_ok());
+    ///
+    /// state.array_get(Top(1), 2).unwrap();
+    /// assert_eq!(state.tonumber(-1).unwrap(), 3.0);
+    ///
+    /// assert!(state.array_get(Top(2), 3).is_err());
+    /// ```
+    pub fn array_get(self: &State, idx: StackIndex, i: usize) -> Result<(), String> {
+        let idx = self._array_index(idx)?;
+        let len = self.getlength(idx) as usize;
+        if i >= len {
+            return Err(format!("Array index {} out of range for array of length {}", i, len));
+        }
+        self.getindex(idx, i as i32);
+        Ok(())
+    }
+
+    /// Pop item on top of stack and append it to array at stack index
+    ///
+    /// Returns the new length of the array.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use mujs;
+    /// use mujs::StackIndex::Top;
+    ///
+    /// let state = mujs::State::new(mujs::JS_STRICT);
+    /// state.newarray();
+    ///
+    /// state.pushstring("Hello");
+    /// assert_eq!(state.array_push(Top(2)).unwrap(), 1);
+    ///
+    /// state.pushstring("world!");
+    /// assert_eq!(state.array_push(Top(2)).unwrap(), 2);
+    ///
+    /// assert_eq!(state.array_len(Top(1)).unwrap(), 2);
+    /// ```
+    pub fn array_push(self: &State, idx: StackIndex) -> Result<usize, String> {
+        let idx = self._array_index(idx)?;
+        if idx == self.gettop() - 1 {
+            return Err("No value on top of the array to push".to_string());
+        }
+        let len = self.getlength(idx);
+        if len == i32::MAX {
+            return Err(format!("Array at stack index {} is full", idx));
+        }
+        self.setindex(idx, len);
+        Ok(len as usize + 1)
+    }
+
     /// Push object representing the global environment record
     pub fn pushglobal(self: &State) {
         unsafe { js_pushglobal((*self.ptr).state) }
     }
 
     /// Get named global variable
-    pub fn getglobal(self: &State, name: &str) {
-        unsafe { js_getglobal((*self.ptr).state, name.to_cstring().unwrap().into_raw()) }
+    pub fn getglobal<K>(self: &State, name: &K) -> Result<(), String>
+        where K: AsPropertyKey + ?Sized
+    {
+        name.with_key(|name| unsafe { js_getglobal((*self.ptr).state, name) })
     }
 
     /// Set named variable with object on top of stack
-    pub fn setglobal(self: &State, name: &str) {
-        unsafe { js_setglobal((*self.ptr).state, name.to_cstring().unwrap().into_raw()) }
+    pub fn setglobal<K>(self: &State, name: &K) -> Result<(), String>
+        where K: AsPropertyKey + ?Sized
+    {
+        name.with_key(|name| unsafe { js_setglobal((*self.ptr).state, name) })
     }
 
     /// Define named global variable
-    pub fn defglobal(self: &State, name: &str, attrs: PropertyAttributes) {
-        unsafe { js_defglobal((*self.ptr).state, name.to_cstring().unwrap().into_raw(), attrs.bits) }
+    pub fn defglobal<K>(self: &State, name: &K, attrs: PropertyAttributes) -> Result<(), String>
+        where K: AsPropertyKey + ?Sized
+    {
+        name.with_key(|name| unsafe { js_defglobal((*self.ptr).state, name, attrs.bits) })
     }
 
-    extern fn _newcfunction_trampoline(js: *const c_void) {
+    extern "C" fn _newcfunction_trampoline(js: *mut js_State) {
+        State::_closure_trampoline(js, CLOSURE_TAG);
+    }
 
-        let s_ptr: *const State = unsafe { js_getcontext(js) as *const State };
-        let state: &State = unsafe{ &(*s_ptr) };
+    extern "C" fn _newcconstructor_trampoline(js: *mut js_State) {
+        State::_closure_trampoline(js, CONSTRUCTOR_TAG);
+    }
 
+    /// Call the closure stored under tag on the current function
+    fn _closure_trampoline(js: *mut js_State, tag: &'static [u8]) {
         let cb_ptr = unsafe {
             js_currentfunction(js);
-            js_getproperty(js, -1, CLOSURE_TAG.to_cstring().unwrap().into_raw());
-            js_touserdata(js, -1, CLOSURE_TAG.to_cstring().unwrap().into_raw())
+            js_getproperty(js, -1, tag.as_ptr() as *const c_char);
+            let ptr = js_touserdata(js, -1, tag.as_ptr() as *const c_char);
+            // Leave only this and the arguments for the closure, the
+            // userdata stays referenced by the function being called.
+            js_pop(js, 2);
+            ptr
         };
 
-        let func: &mut Box<FnMut(&State)> = unsafe { std::mem::transmute(cb_ptr) };
-        func(state);
+        let closure = unsafe { &mut *(cb_ptr as *mut Closure) };
+        let state = unsafe { State::_from_internal(closure.internal) };
+        (closure.func)(&state);
     }
 
-    extern fn _finalize(_: *const c_void, _: *mut c_void) {
+    extern "C" fn _finalize(_: *mut js_State, data: *mut c_void) {
+        unsafe { drop(Box::from_raw(data as *mut Closure)) };
     }
 
     /// push a function object wrapping a rustc closure
     ///
+    /// The closure is owned by the State and must be `Send` since the
+    /// State itself may be moved to another thread. It is dropped when
+    /// the function object is garbage collected or the State is freed.
+    ///
+    /// When called, the closure finds `this` at stack index 0 followed
+    /// by the arguments, padded with undefined up to length, and
+    /// nothing else. The item on top of stack when it returns is the
+    /// result of the call, or undefined if it pushed nothing.
+    ///
     /// # Examples
     ///
     /// ```
@@ -896,30 +1389,90 @@ impl State {
     ///
     /// state.newfunction( move |x| {
     ///     println!("Hello World!");
-    /// }, "myfunc", 0);
-    /// state.setglobal("myfunc");
+    /// }, "myfunc", 0).unwrap();
+    /// state.setglobal("myfunc").unwrap();
     ///
-    /// state.getglobal("myfunc");
+    /// state.getglobal("myfunc").unwrap();
     /// state.pushundefined();
     /// state.call(0);
     /// ```
-    pub fn newfunction<F>(self: &State, func: F, name: &str, length: i32)
+    pub fn newfunction<F>(self: &State, func: F, name: &str, length: i32) -> Result<(), String>
         where F: FnMut(&State),
-              F: 'static
+              F: Send + 'static
     {
-        let cb: Box<Box<FnMut(&State)>> = Box::new(Box::new(func));
-        let cb_ptr = Box::into_raw(cb) as *mut _;
+        let name = name.to_cstring()?;
         unsafe {
             js_newcfunction((*self.ptr).state, Some(::State::_newcfunction_trampoline),
-                            name.to_cstring().unwrap().into_raw(),length);
+                            name.as_ptr(), length);
+        };
+        self._defclosure(func, CLOSURE_TAG);
+        Ok(())
+    }
+
+    fn _defclosure<F>(self: &State, func: F, tag: &'static [u8])
+        where F: FnMut(&State) + Send + 'static
+    {
+        let cb = Box::new(Closure {
+            internal: self.ptr,
+            func: Box::new(func),
+        });
+        let cb_ptr = Box::into_raw(cb) as *mut c_void;
+        unsafe {
             js_pushnull((*self.ptr).state);
-            js_newuserdata((*self.ptr).state,CLOSURE_TAG.to_cstring().unwrap().into_raw(),
+            js_newuserdata((*self.ptr).state, tag.as_ptr() as *const c_char,
                            cb_ptr, Some(::State::_finalize));
-            js_defproperty((*self.ptr).state, -2, CLOSURE_TAG.to_cstring().unwrap().into_raw(),
+            js_defproperty((*self.ptr).state, -2, tag.as_ptr() as *const c_char,
                            (::JS_READONLY | ::JS_DONTENUM | ::JS_DONTCONF).bits);
         };
     }
 
+    /// Pop the prototype object on top of stack and push a
+    /// constructor function object wrapping two rust closures
+    ///
+    /// The function closure is invoked when called as a function
+    /// and the constructor closure when called with `new`. The
+    /// constructor is called with null as this and must push the
+    /// constructed object. The popped object becomes the prototype
+    /// property of the constructor.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use mujs;
+    ///
+    /// let state = mujs::State::new(mujs::JS_STRICT);
+    ///
+    /// state.newobject();
+    /// state.newcconstructor(|js| {
+    ///     js.pushstring("called");
+    /// }, |js| {
+    ///     js.newobject();
+    ///     js.copy(1);
+    ///     js.setproperty(-2, "value").unwrap();
+    /// }, "Box", 1).unwrap();
+    /// state.setglobal("Box").unwrap();
+    ///
+    /// state.dostring("var b = new Box(42);").unwrap();
+    /// state.getglobal("b").unwrap();
+    /// state.getproperty(-1, "value").unwrap();
+    /// assert_eq!(state.tonumber(-1).unwrap(), 42.0);
+    /// ```
+    pub fn newcconstructor<F, C>(self: &State, func: F, constructor: C, name: &str,
+                                 length: i32) -> Result<(), String>
+        where F: FnMut(&State) + Send + 'static,
+              C: FnMut(&State) + Send + 'static
+    {
+        let name = name.to_cstring()?;
+        unsafe {
+            js_newcconstructor((*self.ptr).state, Some(::State::_newcfunction_trampoline),
+                               Some(::State::_newcconstructor_trampoline),
+                               name.as_ptr(), length);
+        }
+        self._defclosure(func, CLOSURE_TAG);
+        self._defclosure(constructor, CONSTRUCTOR_TAG);
+        Ok(())
+    }
+
     /// Test if item on stack is defined
     pub fn isdefined(self: &State, idx: i32) -> bool {
         match unsafe { js_isdefined((*self.ptr).state, idx) } {
@@ -976,38 +1529,336 @@ impl State {
         }
     }
 
-    /// Convert value on stack to string
-    pub fn tostring(self: &State, idx: i32) -> Result<String, String> {
+    fn _tostring<T, F>(self: &State, idx: i32, decode: F) -> Result<T, String>
+        where F: FnOnce(&[u8]) -> Result<T, String>
+    {
         let c_buf: *const c_char = unsafe { js_tostring((*self.ptr).state, idx) };
 
         if c_buf == std::ptr::null() {
             return Err("Null string".to_string())
         }
 
-        Ok(unsafe {
-            CStr::from_ptr(c_buf).to_string_lossy().into_owned()
-        })
+        decode(unsafe { CStr::from_ptr(c_buf).to_bytes() })
     }
 
-    /// Convert value on stack to boolean
-    pub fn toboolean(self: &State, idx: i32) -> Result<bool, String> {
-        match unsafe { js_toboolean((*self.ptr).state, idx) } {
-            0 => Ok(false),
-            _ => Ok(true)
-        }
-    }
+    /// Convert value on stack to string
+    ///
+    /// Invalid sequences, such as lone UTF-16 surrogates, are
+    /// replaced with U+FFFD REPLACEMENT CHARACTER. Use
+    /// State::to_str() to detect them.
+    pub fn tostring(self: &State, idx: i32) -> Result<String, String> {
+        self._tostring(idx, |buf| Ok(utf::decode_str_lossy(buf)))
+    }
+
+    /// Convert value on stack to string, failing on invalid UTF-8
+    ///
+    /// MuJS stores strings as modified UTF-8, the nul character is
+    /// encoded as `0xC0 0x80` and characters created from UTF-16
+    /// escapes in script are stored as one sequence for each
+    /// surrogate half. A high surrogate followed by a low surrogate
+    /// is decoded into a single character while a lone surrogate
+    /// can not be represented in a Rust string and results in an
+    /// error.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use mujs;
+    ///
+    /// let state = mujs::State::new(mujs::JS_STRICT);
+    ///
+    /// state.pushstring("a\0b");
+    /// assert_eq!(state.to_str(0).unwrap(), "a\0b");
+    /// ```
+    pub fn to_str(self: &State, idx: i32) -> Result<String, String> {
+        self._tostring(idx, |buf| {
+            utf::decode_str(buf).map_err(|pos| {
+                format!("Invalid UTF-8 sequence in string at byte {}", pos)
+            })
+        })
+    }
+
+    /// Convert value on stack to bytes, one byte per character
+    ///
+    /// This is the inverse of State::push_bytes() and fails if the
+    /// string contains a character outside the range U+0000 to
+    /// U+00FF.
+    pub fn to_bytes(self: &State, idx: i32) -> Result<Vec<u8>, String> {
+        self._tostring(idx, |buf| {
+            utf::decode_bytes(buf).map_err(|pos| {
+                format!("Character at byte {} in string does not fit in a byte", pos)
+            })
+        })
+    }
+
+    /// Convert value on stack to boolean
+    pub fn toboolean(self: &State, idx: i32) -> Result<bool, String> {
+        match unsafe { js_toboolean((*self.ptr).state, idx) } {
+            0 => Ok(false),
+            _ => Ok(true)
+        }
+    }
 
     /// Convert value on stack to number
     pub fn tonumber(self: &State, idx: i32) -> Result<f64, String> {
         Ok( unsafe { js_tonumber((*self.ptr).state, idx) } )
     }
 
+    /// Convert value on stack to an integer
+    ///
+    /// The number is truncated towards zero, NaN converts to 0 and
+    /// values out of range saturate.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use mujs;
+    ///
+    /// let state = mujs::State::new(mujs::JS_STRICT);
+    /// state.pushnumber(-3.7);
+    /// assert_eq!(state.tointeger(0).unwrap(), -3);
+    /// ```
+    pub fn tointeger(self: &State, idx: i32) -> Result<i32, String> {
+        Ok( unsafe { js_tointeger((*self.ptr).state, idx) } )
+    }
+
+    /// Convert value on stack to a 32-bit signed integer with
+    /// wrap around, as done by bitwise operators
+    pub fn toint32(self: &State, idx: i32) -> Result<i32, String> {
+        Ok( unsafe { js_toint32((*self.ptr).state, idx) } )
+    }
+
+    /// Convert value on stack to a 32-bit unsigned integer with
+    /// wrap around, as done by the `>>>` operator
+    pub fn touint32(self: &State, idx: i32) -> Result<u32, String> {
+        Ok( unsafe { js_touint32((*self.ptr).state, idx) } )
+    }
+
+    /// Convert value on stack to a 16-bit signed integer with wrap
+    /// around
+    pub fn toint16(self: &State, idx: i32) -> Result<i16, String> {
+        Ok( unsafe { js_toint16((*self.ptr).state, idx) } )
+    }
+
+    /// Convert value on stack to a 16-bit unsigned integer with
+    /// wrap around, as used for character codes
+    pub fn touint16(self: &State, idx: i32) -> Result<u16, String> {
+        Ok( unsafe { js_touint16((*self.ptr).state, idx) } )
+    }
+
+    /// Get the type of value on stack as returned by the `typeof`
+    /// operator
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use mujs;
+    ///
+    /// let state = mujs::State::new(mujs::JS_STRICT);
+    /// state.pushnull();
+    /// state.newarray();
+    /// assert_eq!(state.type_of(0), "object");
+    /// assert_eq!(state.type_of(1), "object");
+    /// ```
+    pub fn type_of(self: &State, idx: i32) -> String {
+        unsafe { CStr::from_ptr(js_typeof((*self.ptr).state, idx)).to_string_lossy().into_owned() }
+    }
+
+    /// Test if the value below top of stack is an instance of the
+    /// constructor on top of stack, as the `instanceof` operator
+    ///
+    /// Both values are left on the stack. Fails if the value on
+    /// top of stack is not a function with a prototype object.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use mujs;
+    ///
+    /// let state = mujs::State::new(mujs::JS_STRICT);
+    /// state.newarray();
+    /// state.getglobal("Array").unwrap();
+    /// assert_eq!(state.instanceof().unwrap(), true);
+    /// ```
+    pub fn instanceof(self: &State) -> Result<bool, String> {
+        if !self.iscallable(-1) {
+            return Err("instanceof: invalid operand".to_string());
+        }
+        self.getproperty(-1, "prototype")?;
+        let valid = self.isobject(-1);
+        self.pop(1);
+        if !valid {
+            return Err("instanceof: 'prototype' property is not an object".to_string());
+        }
+        Ok(unsafe { js_instanceof((*self.ptr).state) } != 0)
+    }
+
+    /// Compare the two values on top of stack using the `==`
+    /// operator, leaving them on the stack
+    pub fn equal(self: &State) -> bool {
+        unsafe { js_equal((*self.ptr).state) != 0 }
+    }
+
+    /// Compare the two values on top of stack using the `===`
+    /// operator, leaving them on the stack
+    pub fn strictequal(self: &State) -> bool {
+        unsafe { js_strictequal((*self.ptr).state) != 0 }
+    }
+
+    /// Compare the two values on top of stack as the relational
+    /// operators, leaving them on the stack
+    ///
+    /// Returns the ordering of the value below top of stack
+    /// relative to the value on top, or None if the values are
+    /// unordered such as when either is NaN.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use mujs;
+    /// use std::cmp::Ordering;
+    ///
+    /// let state = mujs::State::new(mujs::JS_STRICT);
+    /// state.pushstring("apple");
+    /// state.pushstring("banana");
+    /// assert_eq!(state.compare(), Some(Ordering::Less));
+    /// ```
+    pub fn compare(self: &State) -> Option<std::cmp::Ordering> {
+        let mut okay: c_int = 0;
+        let res = unsafe { js_compare((*self.ptr).state, &mut okay) };
+        match okay {
+            0 => None,
+            _ => Some(res.cmp(&0))
+        }
+    }
+
+    /// Pop the two values on top of stack and push the result of
+    /// adding them using the `+` operator
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use mujs;
+    ///
+    /// let state = mujs::State::new(mujs::JS_STRICT);
+    /// state.pushstring("Answer: ");
+    /// state.pushnumber(42.0);
+    /// state.concat();
+    /// assert_eq!(state.tostring(0).unwrap(), "Answer: 42");
+    /// ```
+    pub fn concat(self: &State) {
+        unsafe { js_concat((*self.ptr).state) }
+    }
+
+    /// Push an iterator over the enumerable property names of the
+    /// object at stack index
+    ///
+    /// Inherited properties are included unless own is true. Use
+    /// State::nextiterator() to retrieve the names.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use mujs;
+    ///
+    /// let state = mujs::State::new(mujs::JS_STRICT);
+    /// state.dostring("var point = {x: 1, y: 2};").unwrap();
+    /// state.getglobal("point").unwrap();
+    ///
+    /// state.pushiterator(0, true);
+    /// let mut names = Vec::new();
+    /// while let Some(name) = state.nextiterator(-1) {
+    ///     names.push(name);
+    /// }
+    /// assert_eq!(names, vec!["x", "y"]);
+    /// ```
+    pub fn pushiterator(self: &State, idx: i32, own: bool) {
+        unsafe { js_pushiterator((*self.ptr).state, idx, own as c_int) }
+    }
+
+    /// Get the next property name from the iterator at stack index,
+    /// None when all names have been returned
+    pub fn nextiterator(self: &State, idx: i32) -> Option<String> {
+        let name = unsafe { js_nextiterator((*self.ptr).state, idx) };
+        match name.is_null() {
+            true => None,
+            false => Some(utf::decode_str_lossy(unsafe { CStr::from_ptr(name) }.to_bytes()))
+        }
+    }
+
+    /// Pop the value on top of stack and store it in the registry
+    /// under a new unique name, which is returned
+    ///
+    /// The value can be pushed using State::getregistry() with the
+    /// name and must be released using State::unref().
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use mujs;
+    ///
+    /// let state = mujs::State::new(mujs::JS_STRICT);
+    /// state.pushnumber(1.5);
+    /// let reference = state.reference();
+    ///
+    /// state.getregistry(&reference).unwrap();
+    /// assert_eq!(state.tonumber(-1).unwrap(), 1.5);
+    /// state.unref(&reference).unwrap();
+    /// ```
+    pub fn reference(self: &State) -> String {
+        unsafe { CStr::from_ptr(js_ref((*self.ptr).state)).to_string_lossy().into_owned() }
+    }
+
+    /// Release a registry reference created by State::reference()
+    pub fn unref(self: &State, reference: &str) -> Result<(), String> {
+        let reference = reference.to_cstring()?;
+        unsafe { js_unref((*self.ptr).state, reference.as_ptr()) };
+        Ok(())
+    }
+
+    extern "C" fn _report_trampoline(js: *mut js_State, message: *const c_char) {
+        let state = unsafe { State::_borrowed(js) };
+        let message = utf::decode_str_lossy(unsafe { CStr::from_ptr(message) }.to_bytes());
+        // A report raised from within the report function itself is
+        // dropped.
+        let report = state._internal().report.try_borrow_mut();
+        if let Ok(mut report) = report {
+            if let Some(ref mut report) = *report {
+                report(&message);
+            }
+        }
+    }
+
+    /// Set the function receiving warnings and errors reported by
+    /// MuJS, which by default are written to stderr
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use mujs;
+    /// use std::sync::mpsc::channel;
+    ///
+    /// let state = mujs::State::new(mujs::JS_STRICT);
+    /// let (sender, receiver) = channel();
+    /// state.setreport(move |message| sender.send(message.to_string()).unwrap());
+    ///
+    /// state.gc(true);
+    /// assert!(receiver.try_recv().unwrap().starts_with("garbage collected"));
+    /// ```
+    pub fn setreport<F>(self: &State, func: F) where F: FnMut(&str) + Send + 'static {
+        *self._internal().report.borrow_mut() = Some(Box::new(func));
+        unsafe { js_setreport((*self.ptr).state, Some(State::_report_trampoline)) };
+    }
+
     /// Get named registry entry and place on top of stack
     ///
     /// The registry can be used to store references to Javascript
     /// objects accessible from rust, but hidden from Javascript to
     /// prevent tampering.
     ///
+    /// Names starting with `__Rust` are reserved for entries of this
+    /// crate and rejected by the registry methods.
+    ///
     /// # Examples
     ///
     /// ```
@@ -1016,30 +1867,73 @@ impl State {
     /// let state = mujs::State::new(mujs::JS_STRICT);
     ///
     /// state.pushnumber(32.0);
-    /// state.setregistry("number");
+    /// state.setregistry("number").unwrap();
     ///
-    /// state.getregistry("number");
+    /// state.getregistry("number").unwrap();
     /// println!("Number: {:?}", state.tonumber(0).unwrap());
     /// ```
     ///
-    pub fn getregistry(self: &State, name: &str) {
-        unsafe { js_getregistry((*self.ptr).state, name.to_cstring().unwrap().into_raw()) }
+    pub fn getregistry<K>(self: &State, name: &K) -> Result<(), String>
+        where K: AsPropertyKey + ?Sized
+    {
+        self._user_registry_key(name, |name| unsafe { js_getregistry((*self.ptr).state, name) })
     }
 
     /// Store top of stack as named entry in registry
-    pub fn setregistry(self: &State, name: &str) {
-        unsafe { js_setregistry((*self.ptr).state, name.to_cstring().unwrap().into_raw()) }
+    pub fn setregistry<K>(self: &State, name: &K) -> Result<(), String>
+        where K: AsPropertyKey + ?Sized
+    {
+        self._user_registry_key(name, |name| unsafe { js_setregistry((*self.ptr).state, name) })
     }
 
     /// Delete name registry entry
-    pub fn delregistry(self: &State, name: &str) {
-        unsafe { js_delregistry((*self.ptr).state, name.to_cstring().unwrap().into_raw()) }
+    pub fn delregistry<K>(self: &State, name: &K) -> Result<(), String>
+        where K: AsPropertyKey + ?Sized
+    {
+        self._user_registry_key(name, |name| unsafe { js_delregistry((*self.ptr).state, name) })
+    }
+
+    /// Call func with a registry name, rejecting the names starting
+    /// with `__Rust` which are reserved for entries of this crate
+    fn _user_registry_key<K, F>(self: &State, name: &K, func: F) -> Result<(), String>
+        where K: AsPropertyKey + ?Sized,
+              F: FnOnce(*const c_char)
+    {
+        name.with_key(|name| {
+            let reserved = unsafe { CStr::from_ptr(name) }.to_bytes().starts_with(RESERVED_REGISTRY_PREFIX);
+            if reserved {
+                return Err(format!("Registry name '{}' is reserved",
+                                   unsafe { CStr::from_ptr(name) }.to_string_lossy()));
+            }
+            func(name);
+            Ok(())
+        })?
+    }
+
+    /// Get registry entry reserved for this crate
+    pub(crate) fn _getregistry(self: &State, name: &str) -> Result<(), String> {
+        name.with_key(|name| unsafe { js_getregistry((*self.ptr).state, name) })
+    }
+
+    /// Set registry entry reserved for this crate
+    pub(crate) fn _setregistry(self: &State, name: &str) -> Result<(), String> {
+        name.with_key(|name| unsafe { js_setregistry((*self.ptr).state, name) })
+    }
+
+    /// Delete registry entry reserved for this crate
+    pub(crate) fn _delregistry(self: &State, name: &str) -> Result<(), String> {
+        name.with_key(|name| unsafe { js_delregistry((*self.ptr).state, name) })
     }
 }
 
 impl Drop for State {
     fn drop(self: &mut State) {
-        unsafe { js_freestate((*self.ptr).state) };
+        unsafe {
+            // The finalizer of the registry entry leaves owned host
+            // data to be freed here.
+            js_freestate((*self.ptr).state);
+            drop(Box::from_raw(self.ptr));
+        };
     }
 }
 
@@ -1079,6 +1973,13 @@ mod tests {
         assert!(state.loadstring("myscript", "func broken() { return Math.sin(3.2); };").is_err());
     }
 
+    #[test]
+    fn loadstring_with_nul_in_source_is_err() {
+        let state = ::State::new(::StateFlags{bits: 0});
+        assert!(state.loadstring("myscript", "1;\0 2;").is_err());
+        assert_eq!(state.gettop(), 0);
+    }
+
     #[test]
     fn call_with_runtime_error() {
         let state = ::State::new(::StateFlags{bits: 0});
@@ -1099,10 +2000,10 @@ mod tests {
     fn construct_with_success() {
         let state = ::State::new(::StateFlags{bits: 0});
         assert!(state.dostring("function func(a) { this.a = a; }").is_ok());
-        state.getglobal("func");
+        state.getglobal("func").unwrap();
         state.pushnumber(1.1234);
         assert!(state.construct(1).is_ok());
-        state.getproperty(0,"a");
+        state.getproperty(0,"a").unwrap();
         assert_eq!(state.tonumber(1).unwrap(), 1.1234);
     }
 
@@ -1252,7 +2153,7 @@ mod tests {
         state.pushnumber(23.73);
         state.setindex(-2, 2);
         state.setlength(-1, 3);
-        state.setglobal("arr");
+        state.setglobal("arr").unwrap();
 
         assert!(state.loadstring("script", "arr[1];").is_ok());
         state.pushundefined();
@@ -1282,15 +2183,15 @@ mod tests {
     #[test]
     fn newstring_validate_as_string() {
         let state = ::State::new(::JS_STRICT);
-        state.newstring("Hello World!");
+        state.newstring("Hello World!").unwrap();
         assert_eq!(state.tostring(0).unwrap(), "Hello World!");
     }
 
     #[test]
     fn newregexp_test_matching_string() {
         let state = ::State::new(::JS_STRICT);
-        state.newregexp("^Hello (.*)!$", ::JS_REGEXP_G);
-        state.getproperty(0, "test");
+        state.newregexp("^Hello (.*)!$", ::JS_REGEXP_G).unwrap();
+        state.getproperty(0, "test").unwrap();
         state.copy(0);
         state.pushstring("Hello World!");
         state.call(1).unwrap();
@@ -1300,8 +2201,8 @@ mod tests {
     #[test]
     fn newregexp_test_non_matching_string() {
         let state = ::State::new(::JS_STRICT);
-        state.newregexp("^Hello (.*)!$", ::JS_REGEXP_G);
-        state.getproperty(0, "test");
+        state.newregexp("^Hello (.*)!$", ::JS_REGEXP_G).unwrap();
+        state.getproperty(0, "test").unwrap();
         state.copy(0);
         state.pushstring("Hello World.");
         state.call(1).unwrap();
@@ -1350,52 +2251,111 @@ mod tests {
         assert_eq!(state.tostring(0).ok().unwrap(), "Hello Båsse!");
     }
 
+    #[test]
+    fn pushstring_of_substring() {
+        let state = :: State::new(::StateFlags{bits: 0});
+        state.pushstring(&"Hello World!"[0..5]);
+        assert_eq!(state.tostring(0).ok().unwrap(), "Hello");
+    }
+
+    #[test]
+    fn pushstring_with_nul_round_trips() {
+        let state = :: State::new(::StateFlags{bits: 0});
+        state.pushstring("Hello\0World!");
+        state.setglobal("s").unwrap();
+        assert!(state.loadstring("myscript", "s.length").is_ok());
+        state.pushundefined();
+        assert!(state.call(0).is_ok());
+        assert_eq!(state.tonumber(0).unwrap(), 12.0);
+        state.getglobal("s").unwrap();
+        assert_eq!(state.to_str(1).unwrap(), "Hello\0World!");
+    }
+
+    #[test]
+    fn push_bytes_as_char_codes() {
+        let state = :: State::new(::StateFlags{bits: 0});
+        state.push_bytes(&[0x00, 0x41, 0xfe]);
+        state.setglobal("frame").unwrap();
+        assert!(state.loadstring("myscript", "frame.charCodeAt(2)").is_ok());
+        state.pushundefined();
+        assert!(state.call(0).is_ok());
+        assert_eq!(state.tonumber(0).unwrap(), 254.0);
+        state.getglobal("frame").unwrap();
+        assert_eq!(state.to_bytes(1).unwrap(), vec![0x00, 0x41, 0xfe]);
+    }
+
+    #[test]
+    fn to_bytes_with_wide_character_is_err() {
+        let state = :: State::new(::StateFlags{bits: 0});
+        state.pushstring("Båsse €");
+        assert!(state.to_bytes(0).is_err());
+    }
+
+    #[test]
+    fn to_str_with_surrogate_pair() {
+        let state = ::State::new(::StateFlags{bits: 0});
+        assert!(state.loadstring("myscript", "'\\uD83D\\uDE00'").is_ok());
+        state.pushundefined();
+        assert!(state.call(0).is_ok());
+        assert_eq!(state.to_str(0).unwrap(), "\u{1F600}");
+    }
+
+    #[test]
+    fn to_str_with_lone_surrogate_is_err() {
+        let state = ::State::new(::StateFlags{bits: 0});
+        assert!(state.loadstring("myscript", "'a\\uD83Db'").is_ok());
+        state.pushundefined();
+        assert!(state.call(0).is_ok());
+        assert!(state.to_str(0).is_err());
+        assert_eq!(state.tostring(0).unwrap(), "a\u{FFFD}b");
+    }
+
     #[test]
     fn newerror_verify_as_string() {
         let state = ::State::new(::StateFlags{bits: 0});
-        state.newerror("This is an error");
+        state.newerror("This is an error").unwrap();
         assert_eq!(state.tostring(0).ok().unwrap(), "Error: This is an error");
     }
 
     #[test]
     fn newevalerror_verify_as_string() {
         let state = ::State::new(::StateFlags{bits: 0});
-        state.newevalerror("This is an error");
+        state.newevalerror("This is an error").unwrap();
         assert_eq!(state.tostring(0).ok().unwrap(), "EvalError: This is an error");
     }
 
     #[test]
     fn newrangeerror_verify_as_string() {
         let state = ::State::new(::StateFlags{bits: 0});
-        state.newrangeerror("This is an error");
+        state.newrangeerror("This is an error").unwrap();
         assert_eq!(state.tostring(0).ok().unwrap(), "RangeError: This is an error");
     }
 
     #[test]
     fn newreferenceerror_verify_as_string() {
         let state = ::State::new(::StateFlags{bits: 0});
-        state.newreferenceerror("This is an error");
+        state.newreferenceerror("This is an error").unwrap();
         assert_eq!(state.tostring(0).ok().unwrap(), "ReferenceError: This is an error");
     }
 
     #[test]
     fn newsyntaxerror_verify_as_string() {
         let state = ::State::new(::StateFlags{bits: 0});
-        state.newsyntaxerror("This is an error");
+        state.newsyntaxerror("This is an error").unwrap();
         assert_eq!(state.tostring(0).ok().unwrap(), "SyntaxError: This is an error");
     }
 
     #[test]
     fn newtypeerror_verify_as_string() {
         let state = ::State::new(::StateFlags{bits: 0});
-        state.newtypeerror("This is an error");
+        state.newtypeerror("This is an error").unwrap();
         assert_eq!(state.tostring(0).ok().unwrap(), "TypeError: This is an error");
     }
 
     #[test]
     fn newurierror_verify_as_string() {
         let state = ::State::new(::StateFlags{bits: 0});
-        state.newurierror("This is an error");
+        state.newurierror("This is an error").unwrap();
         assert_eq!(state.tostring(0).ok().unwrap(), "URIError: This is an error");
     }
 
@@ -1403,49 +2363,49 @@ mod tests {
     #[should_panic(expected = "Error: This is an error")]
     fn error_should_panic() {
         let state = ::State::new(::StateFlags{bits: 0});
-        state.error("This is an error");
+        state.error("This is an error").unwrap();
     }
 
     #[test]
     #[should_panic(expected = "EvalError: This is an error")]
     fn evalerror_should_panic() {
         let state = ::State::new(::StateFlags{bits: 0});
-        state.evalerror("This is an error");
+        state.evalerror("This is an error").unwrap();
     }
 
     #[test]
     #[should_panic(expected = "RangeError: This is an error")]
     fn rangeerror_should_panic() {
         let state = ::State::new(::StateFlags{bits: 0});
-        state.rangeerror("This is an error");
+        state.rangeerror("This is an error").unwrap();
     }
 
     #[test]
     #[should_panic(expected = "ReferenceError: This is an error")]
     fn referenceerror_should_panic() {
         let state = ::State::new(::StateFlags{bits: 0});
-        state.referenceerror("This is an error");
+        state.referenceerror("This is an error").unwrap();
     }
 
     #[test]
     #[should_panic(expected = "SyntaxError: This is an error")]
     fn syntaxerror_should_panic() {
         let state = ::State::new(::StateFlags{bits: 0});
-        state.syntaxerror("This is an error");
+        state.syntaxerror("This is an error").unwrap();
     }
 
     #[test]
     #[should_panic(expected = "TypeError: This is an error")]
     fn typeerror_should_panic() {
         let state = ::State::new(::StateFlags{bits: 0});
-        state.typeerror("This is an error");
+        state.typeerror("This is an error").unwrap();
     }
 
     #[test]
     #[should_panic(expected = "URIError: This is an error")]
     fn urierror_should_panic() {
         let state = ::State::new(::StateFlags{bits: 0});
-        state.urierror("This is an error");
+        state.urierror("This is an error").unwrap();
     }
 
     #[test]
@@ -1572,93 +2532,730 @@ mod tests {
     }
 
     #[test]
-    fn newfunction_closure_is_called() {
+    fn newfunction_without_result_returns_undefined() {
+        let state = ::State::new(::JS_STRICT);
+        state.newfunction(|_| {}, "nothing", 0).unwrap();
+        state.setglobal("nothing").unwrap();
+        state.dostring("var result = nothing();").unwrap();
+        state.getglobal("result").unwrap();
+        assert!(state.isundefined(-1));
+    }
+
+    #[test]
+    fn newfunction_sees_only_this_and_arguments() {
         let state = ::State::new(::JS_STRICT);
         state.newfunction(|js| {
-            js.pushnumber(1.2345);
-        }, "func", 0);
-        state.setglobal("func");
-        state.getglobal("func");
-        state.pushundefined();
-        assert_eq!(state.call(0).is_ok(), true);
-        assert_eq!(state.tonumber(0).unwrap(), 1.2345);
+            let top = js.gettop();
+            js.pushnumber(top as f64);
+        }, "count", 0).unwrap();
+        state.setglobal("count").unwrap();
+        state.dostring("var result = count(1, 2, 3);").unwrap();
+        state.getglobal("result").unwrap();
+        assert_eq!(state.tonumber(-1).unwrap(), 4.0);
     }
 
     #[test]
-    fn isdefined_on_undefined_is_false() {
-        let state = ::State::new(::StateFlags{bits: 0});
-        state.pushundefined();
-        assert_eq!(state.isdefined(0), false);
+    fn newfunction_closure_is_dropped_by_gc() {
+        use std::sync::Arc;
+        let captured = Arc::new(());
+        let state = ::State::new(::JS_STRICT);
+        let inner = captured.clone();
+        state.newfunction(move |_| { let _ = &inner; }, "keep", 0).unwrap();
+        assert_eq!(Arc::strong_count(&captured), 2);
+        state.pop(1);
+        state.gc(false);
+        assert_eq!(Arc::strong_count(&captured), 1);
     }
 
     #[test]
-    fn isdefined_on_number_is_true() {
-        let state = ::State::new(::StateFlags{bits: 0});
-        state.pushnumber(1.234);
-        assert_eq!(state.isdefined(0), true);
+    fn newfunction_closure_is_dropped_with_state() {
+        use std::sync::Arc;
+        let captured = Arc::new(());
+        {
+            let state = ::State::new(::JS_STRICT);
+            let inner = captured.clone();
+            state.newfunction(move |_| { let _ = &inner; }, "keep", 0).unwrap();
+            state.setglobal("keep").unwrap();
+            state.gc(false);
+            assert_eq!(Arc::strong_count(&captured), 2);
+        }
+        assert_eq!(Arc::strong_count(&captured), 1);
     }
 
     #[test]
-    fn isundefined_on_undefined_is_true() {
-        let state = ::State::new(::StateFlags{bits: 0});
+    fn newfunction_closure_is_called() {
+        let state = ::State::new(::JS_STRICT);
+        state.newfunction(|js| {
+            js.pushnumber(1.2345);
+        }, "func", 0).unwrap();
+        state.setglobal("func").unwrap();
+        state.getglobal("func").unwrap();
         state.pushundefined();
-        assert_eq!(state.isundefined(0), true);
+        assert_eq!(state.call(0).is_ok(), true);
+        assert_eq!(state.tonumber(0).unwrap(), 1.2345);
     }
 
     #[test]
-    fn isundefined_on_number_is_false() {
-        let state = ::State::new(::StateFlags{bits: 0});
-        state.pushnumber(1.234);
-        assert_eq!(state.isundefined(0), false);
+    fn state_is_send() {
+        fn assert_send<T: Send>(_: &T) {}
+        let state = ::State::new(::JS_STRICT);
+        assert_send(&state);
     }
 
     #[test]
-    fn isnull_on_null_is_true() {
+    fn state_moved_to_other_thread() {
         let state = ::State::new(::JS_STRICT);
-        state.pushnull();
-        assert_eq!(state.isnull(0), true);
+        state.dostring("var x = 1.5;").unwrap();
+        let x = std::thread::spawn(move || {
+            state.getglobal("x").unwrap();
+            state.tonumber(0).unwrap()
+        }).join().unwrap();
+        assert_eq!(x, 1.5);
     }
 
     #[test]
-    fn isnull_on_undefined_is_false() {
-        let state = ::State::new(::JS_STRICT);
-        state.pushundefined();
-        assert_eq!(state.isnull(0), false);
+    fn worker_executes_jobs_in_order() {
+        let worker = ::JsWorker::new(::JS_STRICT);
+        worker.execute(|js| js.dostring("var counter = 0;").unwrap()).unwrap();
+        let handles: Vec<_> = (0..10).map(|_| {
+            worker.execute(|js| {
+                js.dostring("counter += 1;").unwrap();
+                js.getglobal("counter").unwrap();
+                let counter = js.tonumber(-1).unwrap();
+                js.pop(1);
+                counter
+            }).unwrap()
+        }).collect();
+        let values: Vec<f64> = handles.into_iter().map(|h| h.wait().unwrap()).collect();
+        assert_eq!(values, (1..11).map(|i| i as f64).collect::<Vec<f64>>());
     }
 
     #[test]
-    fn isboolean_on_boolean_is_true() {
+    fn worker_with_existing_state() {
         let state = ::State::new(::JS_STRICT);
-        state.pushboolean(false);
-        assert_eq!(state.isboolean(0), true);
+        state.newfunction(|js| js.pushnumber(42.0), "answer", 0).unwrap();
+        state.setglobal("answer").unwrap();
+        let worker = ::JsWorker::with_state(state);
+        let answer = worker.execute(|js| {
+            js.getglobal("answer").unwrap();
+            js.pushundefined();
+            js.call(0).unwrap();
+            js.tonumber(-1).unwrap()
+        }).unwrap();
+        assert_eq!(answer.wait().unwrap(), 42.0);
     }
 
     #[test]
-    fn isboolean_on_number_is_false() {
-        let state = ::State::new(::JS_STRICT);
-        state.pushnumber(1.234);
-        assert_eq!(state.isboolean(0), false);
+    fn worker_survives_panicking_job() {
+        let worker = ::JsWorker::new(::JS_STRICT);
+        let failed = worker.execute(|_| -> f64 { panic!("job failed") }).unwrap();
+        assert!(failed.wait().is_err());
+        let ok = worker.execute(|_| 1.0).unwrap();
+        assert_eq!(ok.wait().unwrap(), 1.0);
     }
 
     #[test]
-    fn isnumber_on_number_is_true() {
-        let state = ::State::new(::JS_STRICT);
-        state.pushnumber(1.2345);
-        assert_eq!(state.isnumber(0), true);
+    fn pool_with_failing_init_is_err() {
+        let res = ::StatePool::new(2, ::JS_STRICT, |js| js.dostring("func broken() {"));
+        assert!(res.is_err());
     }
 
     #[test]
-    fn isnumber_on_boolean_is_false() {
-        let state = ::State::new(::JS_STRICT);
-        state.pushboolean(true);
-        assert_eq!(state.isnumber(0), false);
+    fn pool_hands_out_initialized_states() {
+        let pool = ::StatePool::new(2, ::JS_STRICT, |js| js.dostring("var prelude = 1.5;")).unwrap();
+        let a = pool.get();
+        let b = pool.try_get().unwrap();
+        assert!(pool.try_get().is_none());
+        a.getglobal("prelude").unwrap();
+        assert_eq!(a.tonumber(-1).unwrap(), 1.5);
+        b.getglobal("prelude").unwrap();
+        assert_eq!(b.tonumber(-1).unwrap(), 1.5);
+        drop(a);
+        let metrics = pool.metrics();
+        assert_eq!(metrics.size, 2);
+        assert_eq!(metrics.available, 1);
+        assert_eq!(metrics.acquired, 2);
     }
 
     #[test]
-    fn isstring_on_string_is_true() {
-        let state = ::State::new(::JS_STRICT);
-        state.pushstring("Hello world!");
-        assert_eq!(state.isstring(0), true);
+    fn pool_returned_state_has_empty_stack() {
+        let pool = ::StatePool::new(1, ::JS_STRICT, |_| Ok(())).unwrap();
+        pool.get().pushnumber(1.0);
+        assert_eq!(pool.get().gettop(), 0);
+    }
+
+    #[test]
+    fn pool_with_reset_removes_added_globals() {
+        let pool = ::StatePool::with_reset(1, ::JS_STRICT, |js| js.dostring("var prelude = 1;")).unwrap();
+        {
+            let js = pool.get();
+            js.dostring("var added = 2; prelude = 3;").unwrap();
+            js.pushnumber(4.0);
+            js.setglobal("other").unwrap();
+        }
+        let js = pool.get();
+        js.getglobal("added").unwrap();
+        assert!(js.isundefined(-1));
+        js.getglobal("other").unwrap();
+        assert!(js.isundefined(-1));
+        js.getglobal("prelude").unwrap();
+        assert_eq!(js.tonumber(-1).unwrap(), 3.0);
+        assert_eq!(pool.metrics().resets, 1);
+    }
+
+    #[test]
+    fn pool_with_reset_drops_added_userdata() {
+        use std::sync::Arc;
+        let value = Arc::new(());
+        let pool = ::StatePool::with_reset(1, ::JS_STRICT, |js| {
+            js.set_userdata(1u32);
+            Ok(())
+        }).unwrap();
+        {
+            let js = pool.get();
+            js.set_userdata(value.clone());
+            js.set_userdata(2u32);
+            js.set_userdata(3u32);
+        }
+        assert_eq!(Arc::strong_count(&value), 1);
+        assert_eq!(*pool.get().userdata::<u32>().unwrap(), 3);
+    }
+
+    #[test]
+    fn pool_get_timeout_waits_for_release() {
+        let pool = ::StatePool::new(1, ::JS_STRICT, |_| Ok(())).unwrap();
+        let guard = pool.get();
+        let other = pool.clone();
+        let waiter = std::thread::spawn(move || {
+            other.get_timeout(std::time::Duration::from_secs(10)).is_some()
+        });
+        std::thread::sleep(std::time::Duration::from_millis(50));
+        drop(guard);
+        assert!(waiter.join().unwrap());
+        assert_eq!(pool.metrics().waits, 1);
+    }
+
+    struct Shared {
+        value: Option<f64>,
+        waker: Option<std::task::Waker>,
+    }
+
+    struct SharedFuture(std::sync::Arc<std::sync::Mutex<Shared>>);
+
+    impl std::future::Future for SharedFuture {
+        type Output = Result<f64, String>;
+
+        fn poll(self: std::pin::Pin<&mut SharedFuture>,
+                cx: &mut std::task::Context) -> std::task::Poll<Result<f64, String>> {
+            let mut shared = self.0.lock().unwrap();
+            match shared.value.take() {
+                Some(value) => std::task::Poll::Ready(Ok(value)),
+                None => {
+                    shared.waker = Some(cx.waker().clone());
+                    std::task::Poll::Pending
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn async_function_resolves_thenable() {
+        let state = ::State::new(::JS_STRICT);
+        state.new_async_function(|js| {
+            std::future::ready(Ok(js.tostring(1).unwrap() + "!"))
+        }, "shout", 1).unwrap();
+        state.setglobal("shout").unwrap();
+
+        state.dostring("var result = 'none'; shout('hi').then(function (v) { result = v; });").unwrap();
+        state.getglobal("result").unwrap();
+        assert_eq!(state.tostring(-1).unwrap(), "none");
+
+        assert_eq!(state.run_until_idle().unwrap(), 0);
+        state.getglobal("result").unwrap();
+        assert_eq!(state.tostring(-1).unwrap(), "hi!");
+    }
+
+    #[test]
+    fn async_function_error_rejects_thenable() {
+        let state = ::State::new(::JS_STRICT);
+        state.new_async_function(|_| {
+            std::future::ready(Err::<(), String>("Nope".to_string()))
+        }, "fail", 0).unwrap();
+        state.setglobal("fail").unwrap();
+
+        state.dostring("var result; fail().catch(function (e) { result = e.message; });").unwrap();
+        state.run_until_idle().unwrap();
+        state.getglobal("result").unwrap();
+        assert_eq!(state.tostring(-1).unwrap(), "Nope");
+    }
+
+    #[test]
+    fn async_function_settle_error_settles_others() {
+        let state = ::State::new(::JS_STRICT);
+        state.new_async_function(|_| {
+            std::future::ready(Err::<(), String>("bad\0message".to_string()))
+        }, "fail", 0).unwrap();
+        state.setglobal("fail").unwrap();
+        state.new_async_function(|_| std::future::ready(Ok(1)), "one", 0).unwrap();
+        state.setglobal("one").unwrap();
+
+        state.dostring("var result; fail(); one().then(function (v) { result = v; });").unwrap();
+        assert!(state.run_until_idle().is_err());
+        assert_eq!(state.gettop(), 0);
+        assert_eq!(state.run_until_idle().unwrap(), 0);
+        state.getglobal("result").unwrap();
+        assert_eq!(state.tonumber(-1).unwrap(), 1.0);
+    }
+
+    #[test]
+    fn async_function_chained_then() {
+        let state = ::State::new(::JS_STRICT);
+        state.new_async_function(|_| std::future::ready(Ok(1)), "one", 0).unwrap();
+        state.setglobal("one").unwrap();
+
+        state.dostring("var result; one()
+            .then(function (v) { return v + 1; })
+            .then(function (v) { return one().then(function (w) { return v + w; }); })
+            .then(function (v) { result = v; });").unwrap();
+        assert_eq!(state.run_until_idle().unwrap(), 0);
+        state.getglobal("result").unwrap();
+        assert_eq!(state.tonumber(-1).unwrap(), 3.0);
+    }
+
+    #[test]
+    fn async_function_completed_from_other_thread() {
+        let state = ::State::new(::JS_STRICT);
+        let shared = std::sync::Arc::new(std::sync::Mutex::new(Shared { value: None, waker: None }));
+        let future_shared = shared.clone();
+        state.new_async_function(move |_| SharedFuture(future_shared.clone()), "later", 0).unwrap();
+        state.setglobal("later").unwrap();
+
+        state.dostring("var result; later().then(function (v) { result = v; });").unwrap();
+        assert_eq!(state.run_until_idle().unwrap(), 1);
+
+        std::thread::spawn(move || {
+            let mut shared = shared.lock().unwrap();
+            shared.value = Some(4.5);
+            shared.waker.take().unwrap().wake();
+        }).join().unwrap();
+
+        assert_eq!(state.run_until_idle().unwrap(), 0);
+        state.getglobal("result").unwrap();
+        assert_eq!(state.tonumber(-1).unwrap(), 4.5);
+    }
+
+    fn event_loop_log(state: &::State) -> String {
+        state.dostring("var result = log.join();").unwrap();
+        state.getglobal("result").unwrap();
+        let log = state.tostring(-1).unwrap();
+        state.pop(1);
+        log
+    }
+
+    #[test]
+    fn event_loop_runs_timers_in_due_order() {
+        let state = ::State::new(::JS_STRICT);
+        let event_loop = ::EventLoop::with_virtual_clock(&state).unwrap();
+        state.dostring("var log = [];
+            setTimeout(function (a, b) { log.push(a + b); }, 20, 'c', 'd');
+            setTimeout(function () { log.push('b'); }, 10);
+            setTimeout(function () { log.push('a'); });
+            var id = setTimeout(function () { log.push('cleared'); }, 5);
+            clearTimeout(id);").unwrap();
+
+        let start = event_loop.now();
+        event_loop.run().unwrap();
+        assert_eq!(event_loop_log(&state), "a,b,cd");
+        assert_eq!(event_loop.now() - start, std::time::Duration::from_millis(20));
+        assert_eq!(event_loop.pending(), 0);
+    }
+
+    #[test]
+    fn event_loop_interval_until_cleared() {
+        let state = ::State::new(::JS_STRICT);
+        let event_loop = ::EventLoop::with_virtual_clock(&state).unwrap();
+        state.dostring("var log = [], n = 0;
+            var id = setInterval(function () {
+                log.push(++n);
+                if (n === 3)
+                    clearInterval(id);
+            }, 100);").unwrap();
+
+        event_loop.run().unwrap();
+        assert_eq!(event_loop_log(&state), "1,2,3");
+    }
+
+    #[test]
+    fn event_loop_run_until_deadline_keeps_later_timers() {
+        let state = ::State::new(::JS_STRICT);
+        let event_loop = ::EventLoop::with_virtual_clock(&state).unwrap();
+        state.dostring("var log = [];
+            setTimeout(function () { log.push('early'); }, 100);
+            setTimeout(function () { log.push('late'); }, 1000);").unwrap();
+
+        let deadline = event_loop.now() + std::time::Duration::from_millis(500);
+        event_loop.run_until(deadline).unwrap();
+        assert_eq!(event_loop_log(&state), "early");
+        assert_eq!(event_loop.now(), deadline);
+        assert_eq!(event_loop.pending(), 1);
+
+        event_loop.advance(std::time::Duration::from_millis(500));
+        assert_eq!(event_loop.run_once().unwrap(), 1);
+        assert_eq!(event_loop_log(&state), "early,late");
+    }
+
+    #[test]
+    fn event_loop_microtasks_run_before_timers() {
+        let state = ::State::new(::JS_STRICT);
+        let event_loop = ::EventLoop::with_virtual_clock(&state).unwrap();
+        state.dostring("var log = [];
+            setTimeout(function () {
+                queueMicrotask(function () { log.push('micro2'); });
+                log.push('timer1');
+            });
+            setTimeout(function () { log.push('timer2'); });
+            queueMicrotask(function () { log.push('micro1'); });").unwrap();
+
+        event_loop.run().unwrap();
+        assert_eq!(event_loop_log(&state), "micro1,timer1,micro2,timer2");
+    }
+
+    #[test]
+    fn event_loop_timer_error_is_returned() {
+        let state = ::State::new(::JS_STRICT);
+        let event_loop = ::EventLoop::with_virtual_clock(&state).unwrap();
+        state.dostring("setTimeout(function () { throw new Error('Boom'); });").unwrap();
+        assert!(event_loop.run().unwrap_err().contains("Boom"));
+        assert_eq!(state.gettop(), 0);
+    }
+
+    #[test]
+    fn event_loops_sharing_state_keep_their_timers() {
+        let state = ::State::new(::JS_STRICT);
+        let first = ::EventLoop::with_virtual_clock(&state).unwrap();
+        state.dostring("var log = []; setTimeout(function () { log.push('first'); });").unwrap();
+        let second = ::EventLoop::with_virtual_clock(&state).unwrap();
+        state.dostring("setTimeout(function () { log.push('second'); });").unwrap();
+
+        first.run().unwrap();
+        assert_eq!(event_loop_log(&state), "first");
+        second.run().unwrap();
+        assert_eq!(event_loop_log(&state), "first,second");
+    }
+
+    #[test]
+    fn event_loop_set_timeout_requires_function() {
+        let state = ::State::new(::JS_STRICT);
+        let _event_loop = ::EventLoop::new(&state).unwrap();
+        assert!(state.dostring("setTimeout('log.push(1)', 10);").is_err());
+    }
+
+    fn console_messages(state: &::State) -> std::sync::Arc<std::sync::Mutex<Vec<(::ConsoleLevel, String)>>> {
+        let messages = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let sink = messages.clone();
+        state.install_console(move |level, message: &str| {
+            sink.lock().unwrap().push((level, message.to_string()));
+        }).unwrap();
+        messages
+    }
+
+    #[test]
+    fn console_levels_are_routed_to_sink() {
+        let state = ::State::new(::JS_STRICT);
+        let messages = console_messages(&state);
+        state.dostring("console.log('l'); console.info('i'); console.warn('w');
+                        console.error('e'); console.debug('d');").unwrap();
+        assert_eq!(*messages.lock().unwrap(), vec![
+            (::ConsoleLevel::Log, "l".to_string()),
+            (::ConsoleLevel::Info, "i".to_string()),
+            (::ConsoleLevel::Warn, "w".to_string()),
+            (::ConsoleLevel::Error, "e".to_string()),
+            (::ConsoleLevel::Debug, "d".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn console_formats_arguments() {
+        let state = ::State::new(::JS_STRICT);
+        let messages = console_messages(&state);
+        state.dostring("console.log('%s has %d items, %o 100%%', 'cart', 3.9, {a: 1}, [1, 2], null);
+                        console.log(1, 'two', {three: 3});
+                        console.log('%s and %s', 'one');").unwrap();
+        let messages = messages.lock().unwrap();
+        assert_eq!(messages[0].1, "cart has 3 items, {\"a\":1} 100% [1,2] null");
+        assert_eq!(messages[1].1, "1 two {\"three\":3}");
+        assert_eq!(messages[2].1, "one and %s");
+    }
+
+    #[test]
+    fn console_assert_only_reports_failures() {
+        let state = ::State::new(::JS_STRICT);
+        let messages = console_messages(&state);
+        state.dostring("console.assert(true, 'fine'); console.assert(1 > 2, 'x is %d', 5);").unwrap();
+        assert_eq!(*messages.lock().unwrap(), vec![
+            (::ConsoleLevel::Error, "Assertion failed: x is 5".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn console_time_and_trace() {
+        let state = ::State::new(::JS_STRICT);
+        let messages = console_messages(&state);
+        state.dostring("console.time('t'); console.timeEnd('t'); console.timeEnd('t');
+                        console.trace('here');").unwrap();
+        let messages = messages.lock().unwrap();
+        assert_eq!(messages[0].0, ::ConsoleLevel::Log);
+        assert!(messages[0].1.starts_with("t: ") && messages[0].1.ends_with("ms"));
+        assert_eq!(messages[1], (::ConsoleLevel::Warn, "Timer 't' does not exist".to_string()));
+        assert_eq!(messages[2].0, ::ConsoleLevel::Trace);
+        assert!(messages[2].1.starts_with("Trace: here"));
+    }
+
+    #[test]
+    fn check_syntax_does_not_execute() {
+        let state = ::State::new(::JS_STRICT);
+        state.pushnumber(1.0);
+        assert!(state.check_syntax("ok.js", "var executed = true;").is_ok());
+        state.getglobal("executed").unwrap();
+        assert!(state.isundefined(-1));
+        assert_eq!(state.gettop(), 2);
+    }
+
+    #[test]
+    fn check_syntax_reports_location() {
+        let state = ::State::new(::JS_STRICT);
+        let err = state.check_syntax("admin/user.js", "function f() {\n  return 1;\n}\n}").unwrap_err();
+        assert!(err.message.starts_with("SyntaxError"));
+        assert_eq!(err.file.unwrap(), "admin/user.js");
+        assert_eq!(err.line, Some(4));
+        assert_eq!(state.gettop(), 0);
+    }
+
+    #[test]
+    fn loadstring_error_has_location() {
+        let state = ::State::new(::JS_STRICT);
+        let err = state.loadstring("broken.js", "var a = 1;\nvar = 2;").unwrap_err();
+        assert_eq!(err.file.unwrap(), "broken.js");
+        assert_eq!(err.line, Some(2));
+    }
+
+    #[test]
+    fn call_error_has_location() {
+        let state = ::State::new(::JS_STRICT);
+        state.loadstring("lib.js", "function f() {\n  throw new Error('Boom');\n}\nf();").unwrap();
+        state.pushundefined();
+        let err = state.call(0).unwrap_err();
+        assert_eq!(err.message, "Error: Boom");
+        assert_eq!(err.file.unwrap(), "lib.js");
+        assert_eq!(err.line, Some(2));
+    }
+
+    #[test]
+    fn call_error_thrown_string() {
+        let state = ::State::new(::JS_STRICT);
+        state.loadstring("lib.js", "throw 'plain';").unwrap();
+        state.pushundefined();
+        let err = state.call(0).unwrap_err();
+        assert_eq!(err.message, "plain");
+        assert_eq!(err.line, None);
+    }
+
+    #[test]
+    fn call_error_has_stack() {
+        let state = ::State::new(::JS_STRICT);
+        state.newfunction(|js| { let _ = js.error("Native failure"); }, "native", 0).unwrap();
+        state.setglobal("native").unwrap();
+        state.loadstring("app.js", "function outer() {\n  inner();\n}\nfunction inner() {\n  native();\n}\nouter();").unwrap();
+        state.pushundefined();
+
+        let err = state.call(0).unwrap_err();
+        let functions: Vec<_> = err.stack.iter().map(|frame| frame.function.clone()).collect();
+        assert_eq!(&functions[..3], &[Some("native".to_string()),
+                                      Some("inner".to_string()),
+                                      Some("outer".to_string())]);
+        assert_eq!(err.stack[0].line, None);
+        assert_eq!(err.stack[1].file, "app.js");
+        assert_eq!(err.stack[1].line, Some(5));
+        assert_eq!(err.stack[2].line, Some(2));
+        assert_eq!(err.file.unwrap(), "app.js");
+        assert_eq!(err.line, Some(5));
+    }
+
+    #[test]
+    fn push_json_round_trip() {
+        let state = ::State::new(::JS_STRICT);
+        state.push_json("{\"a\": [1, true, null], \"b\": \"\u{e5}\"}").unwrap();
+        assert_eq!(state.to_json(-1, 0).unwrap(), "{\"a\":[1,true,null],\"b\":\"\u{e5}\"}");
+        assert_eq!(state.to_json(0, 2).unwrap(), "{\n  \"a\": [\n    1,\n    true,\n    null\n  ],\n  \"b\": \"\u{e5}\"\n}");
+        assert_eq!(state.gettop(), 1);
+    }
+
+    #[test]
+    fn push_json_malformed_is_err() {
+        let state = ::State::new(::JS_STRICT);
+        let err = state.push_json("{\"a\": }").unwrap_err();
+        assert!(err.message.contains("SyntaxError"));
+        assert_eq!(state.gettop(), 0);
+    }
+
+    #[test]
+    fn to_json_cyclic_is_err() {
+        let state = ::State::new(::JS_STRICT);
+        state.dostring("var a = {}; a.self = a;").unwrap();
+        state.getglobal("a").unwrap();
+        assert!(state.to_json(0, 0).is_err());
+        assert_eq!(state.gettop(), 1);
+    }
+
+    #[test]
+    fn to_json_undefined_is_err() {
+        let state = ::State::new(::JS_STRICT);
+        state.pushundefined();
+        assert!(state.to_json(0, 0).is_err());
+        assert_eq!(state.gettop(), 1);
+    }
+
+    #[cfg(feature = "serde_json")]
+    #[test]
+    fn json_value_round_trip() {
+        let state = ::State::new(::JS_STRICT);
+        let value: ::serde_json::Value = ::serde_json::from_str(
+            "{\"a\": [1, 2.5, true, null], \"b\": {\"c\": \"d\"}}").unwrap();
+        state.push_json_value(&value);
+        assert_eq!(state.to_json_value(0).unwrap(), value);
+        assert_eq!(state.gettop(), 1);
+    }
+
+    #[cfg(feature = "serde_json")]
+    #[test]
+    fn json_value_follows_stringify() {
+        let state = ::State::new(::JS_STRICT);
+        state.dostring("var v = {f: function () {}, u: undefined, n: NaN, a: [undefined, 1]};").unwrap();
+        state.getglobal("v").unwrap();
+        assert_eq!(state.to_json_value(0).unwrap().to_string(), "{\"a\":[null,1],\"n\":null}");
+    }
+
+    #[cfg(feature = "serde_json")]
+    #[test]
+    fn json_value_calls_to_json() {
+        let state = ::State::new(::JS_STRICT);
+        state.dostring("var v = {when: new Date(0), k: {toJSON: function (key) { return key; }}};").unwrap();
+        state.getglobal("v").unwrap();
+        assert_eq!(state.to_json_value(0).unwrap().to_string(),
+                   "{\"k\":\"k\",\"when\":\"1970-01-01T00:00:00.000Z\"}");
+        assert_eq!(state.gettop(), 1);
+    }
+
+    #[cfg(feature = "serde_json")]
+    #[test]
+    fn json_value_too_deep_is_err() {
+        let state = ::State::new(::JS_STRICT);
+        state.dostring("var v = []; for (var i = 0; i < 200; ++i) v = [v];").unwrap();
+        state.getglobal("v").unwrap();
+        assert!(state.to_json_value(0).is_err());
+        assert_eq!(state.gettop(), 1);
+    }
+
+    #[cfg(feature = "serde_json")]
+    #[test]
+    fn json_value_throwing_getter_is_err() {
+        let state = ::State::new(::JS_STRICT);
+        state.dostring("var a = {b: [1, {get x() { throw 1; }}]};
+            var t = {toJSON: function () { throw new Error('Boom'); }};").unwrap();
+        state.getglobal("a").unwrap();
+        assert!(state.to_json_value(0).is_err());
+        assert_eq!(state.gettop(), 1);
+
+        state.getglobal("t").unwrap();
+        assert!(state.to_json_value(1).unwrap_err().message.contains("Boom"));
+        assert_eq!(state.gettop(), 2);
+    }
+
+    #[cfg(feature = "serde_json")]
+    #[test]
+    fn json_value_cyclic_is_err() {
+        let state = ::State::new(::JS_STRICT);
+        state.dostring("var a = {b: {}}; a.b.a = a;").unwrap();
+        state.getglobal("a").unwrap();
+        assert!(state.to_json_value(0).is_err());
+        assert_eq!(state.gettop(), 1);
+    }
+
+    #[test]
+    fn isdefined_on_undefined_is_false() {
+        let state = ::State::new(::StateFlags{bits: 0});
+        state.pushundefined();
+        assert_eq!(state.isdefined(0), false);
+    }
+
+    #[test]
+    fn isdefined_on_number_is_true() {
+        let state = ::State::new(::StateFlags{bits: 0});
+        state.pushnumber(1.234);
+        assert_eq!(state.isdefined(0), true);
+    }
+
+    #[test]
+    fn isundefined_on_undefined_is_true() {
+        let state = ::State::new(::StateFlags{bits: 0});
+        state.pushundefined();
+        assert_eq!(state.isundefined(0), true);
+    }
+
+    #[test]
+    fn isundefined_on_number_is_false() {
+        let state = ::State::new(::StateFlags{bits: 0});
+        state.pushnumber(1.234);
+        assert_eq!(state.isundefined(0), false);
+    }
+
+    #[test]
+    fn isnull_on_null_is_true() {
+        let state = ::State::new(::JS_STRICT);
+        state.pushnull();
+        assert_eq!(state.isnull(0), true);
+    }
+
+    #[test]
+    fn isnull_on_undefined_is_false() {
+        let state = ::State::new(::JS_STRICT);
+        state.pushundefined();
+        assert_eq!(state.isnull(0), false);
+    }
+
+    #[test]
+    fn isboolean_on_boolean_is_true() {
+        let state = ::State::new(::JS_STRICT);
+        state.pushboolean(false);
+        assert_eq!(state.isboolean(0), true);
+    }
+
+    #[test]
+    fn isboolean_on_number_is_false() {
+        let state = ::State::new(::JS_STRICT);
+        state.pushnumber(1.234);
+        assert_eq!(state.isboolean(0), false);
+    }
+
+    #[test]
+    fn isnumber_on_number_is_true() {
+        let state = ::State::new(::JS_STRICT);
+        state.pushnumber(1.2345);
+        assert_eq!(state.isnumber(0), true);
+    }
+
+    #[test]
+    fn isnumber_on_boolean_is_false() {
+        let state = ::State::new(::JS_STRICT);
+        state.pushboolean(true);
+        assert_eq!(state.isnumber(0), false);
+    }
+
+    #[test]
+    fn isstring_on_string_is_true() {
+        let state = ::State::new(::JS_STRICT);
+        state.pushstring("Hello world!");
+        assert_eq!(state.isstring(0), true);
     }
 
     #[test]
@@ -1727,7 +3324,7 @@ mod tests {
     #[test]
     fn isregexp_on_regexp_is_true() {
         let state = ::State::new(::StateFlags{bits: 0});
-        state.newregexp("^Hello (.*)!$", ::JS_REGEXP_G);
+        state.newregexp("^Hello (.*)!$", ::JS_REGEXP_G).unwrap();
         assert_eq!(state.isregexp(0), true);
     }
 
@@ -1744,7 +3341,7 @@ mod tests {
         assert!(state.loadstring("myscript", "var person = {name: \"Tester\", age: 32}; person").is_ok());
         state.newobject();
         assert!(state.call(0).is_ok());
-        assert_eq!(state.hasproperty(0, "age"), true);
+        assert!(state.hasproperty(0, "age").unwrap());
     }
 
     #[test]
@@ -1753,7 +3350,7 @@ mod tests {
         assert!(state.loadstring("myscript", "var person = {name: \"Tester\", age: 32}; person").is_ok());
         state.newobject();
         assert!(state.call(0).is_ok());
-        assert_eq!(state.hasproperty(0, "phone"), false);
+        assert!(!state.hasproperty(0, "phone").unwrap());
     }
 
     #[test]
@@ -1762,7 +3359,7 @@ mod tests {
         assert!(state.loadstring("myscript", "var person = {name: \"Tester\", age: 32}; person").is_ok());
         state.newobject();
         assert!(state.call(0).is_ok());
-        state.getproperty(0, "name");
+        state.getproperty(0, "name").unwrap();
         assert_eq!(state.tostring(1).ok().unwrap(), "Tester");
     }
 
@@ -1772,10 +3369,53 @@ mod tests {
         assert!(state.loadstring("myscript", "var person = {name: \"Tester\", age: 32}; person").is_ok());
         state.newobject();
         assert!(state.call(0).is_ok());
-        state.getproperty(0, "phone");
+        state.getproperty(0, "phone").unwrap();
         assert_eq!(state.isundefined(1), true);
     }
 
+    #[test]
+    fn getproperty_with_nul_in_name_is_err() {
+        let state = ::State::new(::StateFlags{bits: 0});
+        state.newobject();
+        assert!(state.getproperty(0, "pho\0ne").is_err());
+        assert_eq!(state.gettop(), 1);
+    }
+
+    #[test]
+    fn property_key_with_nul_is_err() {
+        assert!(::PropertyKey::new("pho\0ne").is_err());
+    }
+
+    #[test]
+    fn getproperty_with_property_key() {
+        let state = ::State::new(::StateFlags{bits: 0});
+        let name = ::PropertyKey::new("name").unwrap();
+        assert!(state.loadstring("myscript", "var person = {name: \"Tester\", age: 32}; person").is_ok());
+        state.newobject();
+        assert!(state.call(0).is_ok());
+        assert!(state.hasproperty(0, &name).unwrap());
+        state.getproperty(0, &name).unwrap();
+        assert_eq!(state.tostring(1).ok().unwrap(), "Tester");
+    }
+
+    #[test]
+    fn setglobal_with_static_property_key() {
+        const ME: ::PropertyKey = ::PropertyKey::from_static(
+            unsafe { std::ffi::CStr::from_bytes_with_nul_unchecked(b"me\0") });
+        let state = ::State::new(::StateFlags{bits: 0});
+        state.pushnumber(1.234);
+        state.setglobal(&ME).unwrap();
+        state.getglobal("me").unwrap();
+        assert_eq!(state.tonumber(0).unwrap(), 1.234);
+    }
+
+    #[test]
+    fn setglobal_with_nul_in_name_is_err() {
+        let state = ::State::new(::StateFlags{bits: 0});
+        state.pushnumber(1.234);
+        assert!(state.setglobal("m\0e").is_err());
+    }
+
     #[test]
     fn setproperty_on_object_as_number_value() {
         let state = ::State::new(::StateFlags{bits: 0});
@@ -1783,8 +3423,8 @@ mod tests {
         state.newobject();
         assert!(state.call(0).is_ok());
         state.pushnumber(1.234);
-        state.setproperty(0, "age");
-        state.getproperty(0, "age");
+        state.setproperty(0, "age").unwrap();
+        state.getproperty(0, "age").unwrap();
         assert_eq!(state.tonumber(1).unwrap(), 1.234);
     }
 
@@ -1795,8 +3435,8 @@ mod tests {
         state.newobject();
         assert!(state.call(0).is_ok());
         state.pushnumber(1.234);
-        state.setproperty(0, "name");
-        state.getproperty(0, "name");
+        state.setproperty(0, "name").unwrap();
+        state.getproperty(0, "name").unwrap();
         assert_eq!(state.tonumber(1).unwrap(), 1.234);
     }
 
@@ -1808,8 +3448,8 @@ mod tests {
         state.newobject();
         assert!(state.call(0).is_ok());
         state.pushnumber(1.234);
-        state.setproperty(0, "phone");
-        state.getproperty(0, "phone");
+        state.setproperty(0, "phone").unwrap();
+        state.getproperty(0, "phone").unwrap();
         assert_eq!(state.tonumber(1).unwrap(), 1.234);
     }
 
@@ -1818,11 +3458,11 @@ mod tests {
         let state = ::State::new(::StateFlags{bits: 0});
         state.newobject();
         state.pushnumber(1.234);
-        state.defproperty(0, "age", ::JS_READONLY);
+        state.defproperty(0, "age", ::JS_READONLY).unwrap();
 
         state.pushnumber(1.0);
-        state.setproperty(0, "age");
-        state.getproperty(0, "age");
+        state.setproperty(0, "age").unwrap();
+        state.getproperty(0, "age").unwrap();
         assert_eq!(state.tonumber(1).unwrap(), 1.234);
     }
 
@@ -1831,11 +3471,11 @@ mod tests {
         let state = ::State::new(::StateFlags{bits: 0});
         state.newobject();
         state.pushnumber(1.234);
-        state.defproperty(0, "age", ::PropertyAttributes{bits: 0});
+        state.defproperty(0, "age", ::PropertyAttributes{bits: 0}).unwrap();
 
         state.pushnumber(1.0);
-        state.setproperty(0, "age");
-        state.getproperty(0, "age");
+        state.setproperty(0, "age").unwrap();
+        state.getproperty(0, "age").unwrap();
         assert_eq!(state.tonumber(1).unwrap(), 1.0);
     }
 
@@ -1848,15 +3488,15 @@ mod tests {
 
         state.newfunction( move |x| {
             x.pushnumber(55.0);
-        }, "age.getter", 0);
+        }, "age.getter", 0).unwrap();
         state.pushnull();
-        state.defaccessor(0, "age", ::JS_DONTENUM);
+        state.defaccessor(0, "age", ::JS_DONTENUM).unwrap();
 
         state.pushnumber(1.1234);
-        state.setproperty(0, "age");
+        state.setproperty(0, "age").unwrap();
 
         // When
-        state.getproperty(0, "age");
+        state.getproperty(0, "age").unwrap();
 
         // Then
         assert_eq!(state.tonumber(1).unwrap(), 55.0);
@@ -1870,13 +3510,13 @@ mod tests {
         state.newobject();
         state.pushnull();
         state.pushnull();
-        state.defaccessor(0, "age", ::JS_DONTENUM);
+        state.defaccessor(0, "age", ::JS_DONTENUM).unwrap();
 
         state.pushnumber(1.1234);
-        state.setproperty(0, "age");
+        state.setproperty(0, "age").unwrap();
 
         // When
-        state.getproperty(0, "age");
+        state.getproperty(0, "age").unwrap();
 
         // Then
         assert_eq!(state.tonumber(1).unwrap(), 1.1234);
@@ -1896,8 +3536,8 @@ mod tests {
         state.newobject();
         assert!(state.call(0).is_ok());
 
-        state.delproperty(0, "func");
-        state.getproperty(0, "func");
+        state.delproperty(0, "func").unwrap();
+        state.getproperty(0, "func").unwrap();
         assert_eq!(state.tostring(1).unwrap(), "function (a,b) { ... }");
     }
 
@@ -1912,106 +3552,289 @@ mod tests {
 
         assert!(state.loadstring("myscript", source).is_ok());
         state.newobject();
-        assert!(state.call(0).is_ok());
-
-        state.delproperty(0, "func");
-        state.getproperty(0, "func");
-        assert_eq!(state.tostring(1).unwrap(), "undefined");
+        assert!(state.call(0).is_ok());
+
+        state.delproperty(0, "func").unwrap();
+        state.getproperty(0, "func").unwrap();
+        assert_eq!(state.tostring(1).unwrap(), "undefined");
+    }
+
+    #[test]
+    fn getlength_on_empty_array() {
+        let state = ::State::new(::JS_STRICT);
+        assert!(state.loadstring("myscript", "[]").is_ok());
+        state.pushundefined();
+        assert!(state.call(0).is_ok());
+        assert_eq!(state.getlength(0), 0);
+    }
+
+    #[test]
+    fn getlength_on_non_empty_array() {
+        let state = ::State::new(::JS_STRICT);
+        assert!(state.loadstring("myscript", "[1, 2, 3]").is_ok());
+        state.pushundefined();
+        assert!(state.call(0).is_ok());
+        assert_eq!(state.getlength(0), 3);
+    }
+
+    #[test]
+    fn hasindex_on_empty_array() {
+        let state = ::State::new(::JS_STRICT);
+        assert!(state.loadstring("myscript", "[]").is_ok());
+        state.pushundefined();
+        assert!(state.call(0).is_ok());
+        assert_eq!(state.hasindex(0, 1), false);
+    }
+
+    #[test]
+    fn hasindex_on_non_existing_index() {
+        let state = ::State::new(::JS_STRICT);
+        assert!(state.loadstring("myscript", "[1,2,3]").is_ok());
+        state.pushundefined();
+        assert!(state.call(0).is_ok());
+        assert_eq!(state.hasindex(0, 4), false);
+    }
+
+    #[test]
+    fn hasindex_on_existing_index() {
+        let state = ::State::new(::JS_STRICT);
+        assert!(state.loadstring("myscript", "[1,2,3]").is_ok());
+        state.pushundefined();
+        assert!(state.call(0).is_ok());
+        assert_eq!(state.hasindex(0, 2), true);
+    }
+
+    #[test]
+    fn hasindex_on_non_array() {
+        let state = ::State::new(::JS_STRICT);
+        assert!(state.loadstring("myscript", "1").is_ok());
+        state.pushundefined();
+        assert!(state.call(0).is_ok());
+        assert_eq!(state.hasindex(0, 2), false);
+    }
+
+    #[test]
+    fn getindex_on_empty_array() {
+        let state = ::State::new(::JS_STRICT);
+        assert!(state.loadstring("myscript", "[]").is_ok());
+        state.pushundefined();
+        assert!(state.call(0).is_ok());
+        state.getindex(0, 0);
+        assert_eq!(state.isundefined(1), true);
+    }
+
+    #[test]
+    fn getindex_on_existing_index() {
+        let state = ::State::new(::JS_STRICT);
+        assert!(state.loadstring("myscript", "[1,2,3]").is_ok());
+        state.pushundefined();
+        assert!(state.call(0).is_ok());
+        state.getindex(0, 2);
+        assert_eq!(state.tonumber(1).unwrap(), 3.0);
+    }
+
+    #[test]
+    fn delindex_on_empty_array() {
+        let state = ::State::new(::JS_STRICT);
+        assert!(state.loadstring("myscript", "[]").is_ok());
+        state.pushundefined();
+        assert!(state.call(0).is_ok());
+        state.delindex(0, 2);
+        assert_eq!(state.getlength(0), 0);
+    }
+
+    #[test]
+    fn delindex_on_non_empty_array() {
+        let state = ::State::new(::JS_STRICT);
+        assert!(state.loadstring("myscript", "[1,2,3]").is_ok());
+        state.pushundefined();
+        assert!(state.call(0).is_ok());
+        state.delindex(0, 2);
+        state.getindex(0, 2);
+        assert_eq!(state.isundefined(1), true);
+    }
+
+    #[test]
+    fn abs_index_on_empty_stack_is_err() {
+        let state = ::State::new(::JS_STRICT);
+        assert!(state.abs_index(::StackIndex::Absolute(0)).is_err());
+        assert!(state.abs_index(::StackIndex::Top(1)).is_err());
+    }
+
+    #[test]
+    fn abs_index_of_top_zero_is_err() {
+        let state = ::State::new(::JS_STRICT);
+        state.pushnumber(1.0);
+        assert!(state.abs_index(::StackIndex::Top(0)).is_err());
+    }
+
+    #[test]
+    fn abs_index_from_top() {
+        let state = ::State::new(::JS_STRICT);
+        state.pushnumber(1.0);
+        state.pushnumber(2.0);
+        state.pushnumber(3.0);
+        assert_eq!(state.abs_index(::StackIndex::Top(3)).unwrap(), 0);
+        assert_eq!(state.abs_index(::StackIndex::Absolute(2)).unwrap(), 2);
+    }
+
+    #[test]
+    fn array_len_on_non_array_is_err() {
+        let state = ::State::new(::JS_STRICT);
+        state.newobject();
+        assert!(state.array_len(::StackIndex::Top(1)).is_err());
     }
 
     #[test]
-    fn getlength_on_empty_array() {
+    fn array_get_on_existing_index() {
         let state = ::State::new(::JS_STRICT);
-        assert!(state.loadstring("myscript", "[]").is_ok());
+        assert!(state.loadstring("myscript", "[1,2,3]").is_ok());
         state.pushundefined();
         assert!(state.call(0).is_ok());
-        assert_eq!(state.getlength(0), 0);
+        assert_eq!(state.array_len(::StackIndex::Absolute(0)).unwrap(), 3);
+        state.array_get(::StackIndex::Absolute(0), 1).unwrap();
+        assert_eq!(state.tonumber(1).unwrap(), 2.0);
     }
 
     #[test]
-    fn getlength_on_non_empty_array() {
+    fn array_get_out_of_range_is_err() {
         let state = ::State::new(::JS_STRICT);
-        assert!(state.loadstring("myscript", "[1, 2, 3]").is_ok());
+        assert!(state.loadstring("myscript", "[1,2,3]").is_ok());
         state.pushundefined();
         assert!(state.call(0).is_ok());
-        assert_eq!(state.getlength(0), 3);
+        assert!(state.array_get(::StackIndex::Absolute(0), 3).is_err());
+        assert!(state.array_get(::StackIndex::Absolute(1), 0).is_err());
+        assert_eq!(state.gettop(), 1);
     }
 
     #[test]
-    fn hasindex_on_empty_array() {
+    fn array_push_appends_items() {
         let state = ::State::new(::JS_STRICT);
-        assert!(state.loadstring("myscript", "[]").is_ok());
-        state.pushundefined();
-        assert!(state.call(0).is_ok());
-        assert_eq!(state.hasindex(0, 1), false);
+        state.newarray();
+        state.pushnumber(1.0);
+        assert_eq!(state.array_push(::StackIndex::Top(2)).unwrap(), 1);
+        state.pushnumber(2.0);
+        assert_eq!(state.array_push(::StackIndex::Absolute(0)).unwrap(), 2);
+        assert_eq!(state.gettop(), 1);
+        state.getindex(0, 1);
+        assert_eq!(state.tonumber(1).unwrap(), 2.0);
     }
 
     #[test]
-    fn hasindex_on_non_existing_index() {
+    fn array_push_without_value_is_err() {
         let state = ::State::new(::JS_STRICT);
-        assert!(state.loadstring("myscript", "[1,2,3]").is_ok());
-        state.pushundefined();
-        assert!(state.call(0).is_ok());
-        assert_eq!(state.hasindex(0, 4), false);
+        state.newarray();
+        assert!(state.array_push(::StackIndex::Top(1)).is_err());
     }
 
     #[test]
-    fn hasindex_on_existing_index() {
+    fn checked_stack_methods_reject_index_outside_of_stack() {
         let state = ::State::new(::JS_STRICT);
-        assert!(state.loadstring("myscript", "[1,2,3]").is_ok());
-        state.pushundefined();
-        assert!(state.call(0).is_ok());
-        assert_eq!(state.hasindex(0, 2), true);
+        state.pushnumber(1.0);
+        assert!(state.copy_at(::StackIndex::Absolute(1)).is_err());
+        assert!(state.remove_at(::StackIndex::Top(2)).is_err());
+        assert!(state.isnumber_at(::StackIndex::Top(0)).is_err());
+        assert!(state.tostring_at(::StackIndex::Absolute(1)).is_err());
+        assert!(state.getindex_at(::StackIndex::Top(2), 0).is_err());
+        assert_eq!(state.gettop(), 1);
+        assert!(state.isnumber_at(::StackIndex::Top(1)).unwrap());
+        assert_eq!(state.tonumber_at(::StackIndex::Absolute(0)).unwrap(), 1.0);
     }
 
     #[test]
-    fn hasindex_on_non_array() {
+    fn checked_property_methods_on_undefined_are_err() {
         let state = ::State::new(::JS_STRICT);
-        assert!(state.loadstring("myscript", "1").is_ok());
         state.pushundefined();
-        assert!(state.call(0).is_ok());
-        assert_eq!(state.hasindex(0, 2), false);
+        state.pushnumber(1.0);
+        assert!(state.getproperty_at(::StackIndex::Absolute(0), "x").is_err());
+        assert!(state.setproperty_at(::StackIndex::Absolute(0), "x").is_err());
+        assert!(state.getlength_at(::StackIndex::Absolute(0)).is_err());
+        assert!(state.pushiterator_at(::StackIndex::Absolute(0), true).is_err());
+        assert_eq!(state.gettop(), 2);
     }
 
     #[test]
-    fn getindex_on_empty_array() {
+    fn checked_setters_need_value_above_object() {
         let state = ::State::new(::JS_STRICT);
-        assert!(state.loadstring("myscript", "[]").is_ok());
-        state.pushundefined();
-        assert!(state.call(0).is_ok());
-        state.getindex(0, 0);
-        assert_eq!(state.isundefined(1), true);
+        state.newarray();
+        assert!(state.setindex_at(::StackIndex::Top(1), 0).is_err());
+        state.pushstring("a");
+        state.setindex_at(::StackIndex::Top(2), 0).unwrap();
+        state.pushnull();
+        assert!(state.defaccessor_at(::StackIndex::Top(2), "x", ::JS_READONLY).is_err());
+        assert_eq!(state.getlength_at(::StackIndex::Absolute(0)).unwrap(), 1);
+        state.getindex_at(::StackIndex::Absolute(0), 0).unwrap();
+        assert_eq!(state.tostring_at(::StackIndex::Top(1)).unwrap(), "a");
     }
 
     #[test]
-    fn getindex_on_existing_index() {
+    fn object_builder_leaves_one_value() {
         let state = ::State::new(::JS_STRICT);
-        assert!(state.loadstring("myscript", "[1,2,3]").is_ok());
+        state.object()
+            .set("a", 1)
+            .set("b", vec![1, 2])
+            .set_with("c", |js| js.object().set("d", true).finish())
+            .finish()
+            .unwrap();
+        assert_eq!(state.gettop(), 1);
+        state.setglobal("obj").unwrap();
+        assert!(state.loadstring("myscript", "obj.a + obj.b[1] + (obj.c.d ? 1 : 0)").is_ok());
         state.pushundefined();
         assert!(state.call(0).is_ok());
-        state.getindex(0, 2);
-        assert_eq!(state.tonumber(1).unwrap(), 3.0);
+        assert_eq!(state.tonumber(0).unwrap(), 4.0);
     }
 
     #[test]
-    fn delindex_on_empty_array() {
+    fn object_builder_define_read_only() {
+        let state = ::State::new(::StateFlags{bits: 0});
+        state.object()
+            .define("age", 1.234, ::JS_READONLY)
+            .finish()
+            .unwrap();
+        state.pushnumber(1.0);
+        state.setproperty(0, "age").unwrap();
+        state.getproperty(0, "age").unwrap();
+        assert_eq!(state.tonumber(1).unwrap(), 1.234);
+    }
+
+    #[test]
+    fn object_builder_with_invalid_name_is_err() {
         let state = ::State::new(::JS_STRICT);
-        assert!(state.loadstring("myscript", "[]").is_ok());
         state.pushundefined();
-        assert!(state.call(0).is_ok());
-        state.delindex(0, 2);
-        assert_eq!(state.getlength(0), 0);
+        let res = state.object()
+            .set("a", 1)
+            .set("b\0", 2)
+            .set("c", 3)
+            .finish();
+        assert!(res.is_err());
+        assert_eq!(state.gettop(), 1);
     }
 
     #[test]
-    fn delindex_on_non_empty_array() {
+    fn object_builder_set_with_pushing_nothing_is_err() {
         let state = ::State::new(::JS_STRICT);
-        assert!(state.loadstring("myscript", "[1,2,3]").is_ok());
-        state.pushundefined();
-        assert!(state.call(0).is_ok());
-        state.delindex(0, 2);
+        let res = state.object()
+            .set_with("a", |_| Ok(()))
+            .finish();
+        assert!(res.is_err());
+        assert_eq!(state.gettop(), 0);
+    }
+
+    #[test]
+    fn array_builder_appends_items() {
+        let state = ::State::new(::JS_STRICT);
+        state.array()
+            .push("one")
+            .push(2)
+            .push_with(|js| js.array().push(3).finish())
+            .finish()
+            .unwrap();
+        assert_eq!(state.gettop(), 1);
+        assert_eq!(state.getlength(0), 3);
+        state.getindex(0, 0);
+        assert_eq!(state.tostring(1).unwrap(), "one");
         state.getindex(0, 2);
-        assert_eq!(state.isundefined(1), true);
+        assert!(state.isarray(2));
     }
 
     #[test]
@@ -2019,13 +3842,13 @@ mod tests {
         let state = ::State::new(::StateFlags{bits: 0});
         state.newobject();
         state.pushnumber(1.234);
-        state.setproperty(-2, "age");
-        state.setglobal("me");
+        state.setproperty(-2, "age").unwrap();
+        state.setglobal("me").unwrap();
 
         assert!(state.loadstring("myscript", "me").is_ok());
         state.newobject();
         assert!(state.call(0).is_ok());
-        state.getproperty(0, "age");
+        state.getproperty(0, "age").unwrap();
         assert_eq!(state.tostring(1).unwrap(), "1.234");
     }
 
@@ -2035,19 +3858,19 @@ mod tests {
         let state = ::State::new(::StateFlags{bits: 0});
         state.newobject();
         state.pushnumber(1.234);
-        state.setproperty(-2, "age");
-        state.defglobal("me", attrs);
+        state.setproperty(-2, "age").unwrap();
+        state.defglobal("me", attrs).unwrap();
 
         state.newobject();
         state.pushnumber(1.0);
-        state.setproperty(-2, "age");
-        state.setglobal("me");
+        state.setproperty(-2, "age").unwrap();
+        state.setglobal("me").unwrap();
 
         assert!(state.loadstring("myscript", "me").is_ok());
         state.newobject();
         assert!(state.call(0).is_ok());
 
-        state.getproperty(0, "age");
+        state.getproperty(0, "age").unwrap();
         assert_eq!(state.tonumber(1).unwrap(), 1.234);
     }
 
@@ -2057,19 +3880,19 @@ mod tests {
         let state = ::State::new(::StateFlags{bits: 0});
         state.newobject();
         state.pushnumber(1.234);
-        state.setproperty(-2, "age");
-        state.defglobal("me", attrs);
+        state.setproperty(-2, "age").unwrap();
+        state.defglobal("me", attrs).unwrap();
 
         state.newobject();
         state.pushnumber(1.0);
-        state.setproperty(-2, "age");
-        state.setglobal("me");
+        state.setproperty(-2, "age").unwrap();
+        state.setglobal("me").unwrap();
 
         assert!(state.loadstring("myscript", "me").is_ok());
         state.newobject();
         assert!(state.call(0).is_ok());
 
-        state.getproperty(0, "age");
+        state.getproperty(0, "age").unwrap();
         assert_eq!(state.tonumber(1).unwrap(), 1.0);
     }
 
@@ -2080,24 +3903,530 @@ mod tests {
         state.newobject();
         assert!(state.call(0).is_ok());
 
-        state.getglobal("me");
-        state.getproperty(1, "age");
+        state.getglobal("me").unwrap();
+        state.getproperty(1, "age").unwrap();
         assert_eq!(state.tostring(2).unwrap(), "1.234");
     }
 
     #[test]
     fn getregistry_unknown_name() {
         let state = ::State::new(::JS_STRICT);
-        state.getregistry("hidden");
+        state.getregistry("hidden").unwrap();
         assert_eq!(state.isundefined(0), true);
     }
 
+    #[test]
+    fn registry_rejects_reserved_names() {
+        let state = ::State::new(::JS_STRICT);
+        assert!(state.getregistry("__RustState__").is_err());
+        assert!(state.delregistry("__RustState__").is_err());
+        state.pushnull();
+        assert!(state.setregistry("__RustState__").is_err());
+        state.pop(1);
+
+        state.newfunction(|js| js.pushnumber(1.0), "one", 0).unwrap();
+        state.setglobal("one").unwrap();
+        state.dostring("var result = one();").unwrap();
+        state.getglobal("result").unwrap();
+        assert_eq!(state.tonumber(-1).unwrap(), 1.0);
+    }
+
     #[test]
     fn getregistry_with_existing_name() {
         let state = ::State::new(::JS_STRICT);
         state.pushnumber(1.234);
-        state.setregistry("hidden");
-        state.getregistry("hidden");
+        state.setregistry("hidden").unwrap();
+        state.getregistry("hidden").unwrap();
         assert_eq!(state.tonumber(0).unwrap(), 1.234);
     }
+
+    #[test]
+    fn integer_conversions_wrap_around() {
+        let state = ::State::new(::JS_STRICT);
+        state.pushnumber(4294967297.5);
+        assert_eq!(state.toint32(0).unwrap(), 1);
+        assert_eq!(state.touint32(0).unwrap(), 1);
+        state.pushnumber(-1.0);
+        assert_eq!(state.touint32(1).unwrap(), 4294967295);
+        assert_eq!(state.toint16(1).unwrap(), -1);
+        assert_eq!(state.touint16(1).unwrap(), 65535);
+        state.pushnumber(f64::NAN);
+        assert_eq!(state.tointeger(2).unwrap(), 0);
+    }
+
+    #[test]
+    fn type_of_values() {
+        let state = ::State::new(::JS_STRICT);
+        state.pushundefined();
+        state.pushboolean(true);
+        state.pushstring("text");
+        state.getglobal("Object").unwrap();
+        let types: Vec<String> = (0..4).map(|idx| state.type_of(idx)).collect();
+        assert_eq!(types, vec!["undefined", "boolean", "string", "function"]);
+    }
+
+    #[test]
+    fn instanceof_with_invalid_operand() {
+        let state = ::State::new(::JS_STRICT);
+        state.newobject();
+        state.getglobal("Array").unwrap();
+        assert!(!state.instanceof().unwrap());
+        state.pushnumber(1.0);
+        assert!(state.instanceof().is_err());
+        assert_eq!(state.gettop(), 3);
+    }
+
+    #[test]
+    fn equal_and_strictequal() {
+        let state = ::State::new(::JS_STRICT);
+        state.pushnumber(1.0);
+        state.pushstring("1");
+        assert!(state.equal());
+        assert!(!state.strictequal());
+        assert_eq!(state.gettop(), 2);
+    }
+
+    #[test]
+    fn compare_with_nan_is_unordered() {
+        let state = ::State::new(::JS_STRICT);
+        state.pushnumber(2.0);
+        state.pushnumber(1.0);
+        assert_eq!(state.compare(), Some(::std::cmp::Ordering::Greater));
+        state.pushnumber(f64::NAN);
+        assert_eq!(state.compare(), None);
+    }
+
+    #[test]
+    fn concat_numbers_adds() {
+        let state = ::State::new(::JS_STRICT);
+        state.pushnumber(1.0);
+        state.pushnumber(2.0);
+        state.concat();
+        assert_eq!(state.gettop(), 1);
+        assert_eq!(state.tonumber(0).unwrap(), 3.0);
+    }
+
+    #[test]
+    fn pushiterator_includes_inherited() {
+        let state = ::State::new(::JS_STRICT);
+        state.dostring("var base = {a: 1}; var derived = Object.create(base); derived.b = 2;").unwrap();
+        state.getglobal("derived").unwrap();
+        for &(own, ref expected) in &[(true, vec!["b"]), (false, vec!["b", "a"])] {
+            state.pushiterator(0, own);
+            let mut names = Vec::new();
+            while let Some(name) = state.nextiterator(-1) {
+                names.push(name);
+            }
+            state.pop(1);
+            assert_eq!(names, *expected);
+        }
+    }
+
+    #[test]
+    fn unref_removes_reference() {
+        let state = ::State::new(::JS_STRICT);
+        state.pushstring("value");
+        let reference = state.reference();
+        assert_eq!(state.gettop(), 0);
+        state.unref(&reference).unwrap();
+        state.getregistry(&reference).unwrap();
+        assert!(state.isundefined(0));
+    }
+
+    #[test]
+    fn newcconstructor_called_as_function() {
+        let state = ::State::new(::JS_STRICT);
+        state.newobject();
+        state.newcconstructor(|js| js.pushstring("function"),
+                              |js| { js.newobject(); }, "Thing", 0).unwrap();
+        state.setglobal("Thing").unwrap();
+        state.dostring("var a = Thing(), b = new Thing();").unwrap();
+        state.getglobal("a").unwrap();
+        state.getglobal("b").unwrap();
+        assert_eq!(state.tostring(0).unwrap(), "function");
+        assert!(state.isobject(1));
+    }
+
+    #[test]
+    fn with_userdata_wrong_tag_or_type() {
+        let state = ::State::new(::JS_STRICT);
+        state.pushnull();
+        state.newuserdata("Name", "Tester".to_string()).unwrap();
+        assert!(state.isuserdata(0, "Name").unwrap());
+        assert!(!state.isuserdata(0, "Other").unwrap());
+        assert!(state.with_userdata(0, "Other", |_: &mut String| ()).is_err());
+        assert!(state.with_userdata(0, "Name", |_: &mut u32| ()).is_err());
+        assert_eq!(state.with_userdata(0, "Name", |name: &mut String| name.clone()).unwrap(), "Tester");
+    }
+
+    #[test]
+    fn with_userdata_on_internal_userdata_is_err() {
+        let state = ::State::new(::JS_STRICT);
+        state.newfunction(|_| {}, "f", 0).unwrap();
+        state.getproperty(0, "__RustClosure__").unwrap();
+        assert!(!state.isuserdata(1, "__RustClosure__").unwrap());
+        assert!(state.with_userdata(1, "__RustClosure__", |_: &mut u32| ()).is_err());
+    }
+
+    #[test]
+    fn newuserdatax_throwing_callback_releases_value() {
+        let state = ::State::new(::JS_STRICT);
+        state.pushnull();
+        state.newuserdatax("Store", String::new(), |_, _, _| false, |js, value: &mut String, _| {
+            *value = js.tostring(-1).unwrap();
+            true
+        }, |_, _, _| false).unwrap();
+        state.setglobal("store").unwrap();
+
+        assert!(state.dostring("store.x = {toString: function () { throw new Error('Boom'); }};").is_err());
+        state.dostring("store.x = 'ok';").unwrap();
+        state.getglobal("store").unwrap();
+        assert_eq!(state.with_userdata(-1, "Store", |value: &mut String| value.clone()).unwrap(), "ok");
+    }
+
+    #[test]
+    fn with_userdata_panic_releases_value() {
+        let state = ::State::new(::JS_STRICT);
+        state.pushnull();
+        state.newuserdata("Counter", 0u32).unwrap();
+        let res = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            state.with_userdata(0, "Counter", |_: &mut u32| panic!("Boom"))
+        }));
+        assert!(res.is_err());
+        assert_eq!(state.with_userdata(0, "Counter", |count: &mut u32| *count).unwrap(), 0);
+    }
+
+    #[test]
+    fn newuserdata_drops_value_when_freed() {
+        use std::sync::Arc;
+        let value = Arc::new(());
+        {
+            let state = ::State::new(::JS_STRICT);
+            state.pushnull();
+            state.newuserdata("Shared", value.clone()).unwrap();
+            assert_eq!(Arc::strong_count(&value), 2);
+        }
+        assert_eq!(Arc::strong_count(&value), 1);
+    }
+
+    #[test]
+    fn newuserdatax_delete_and_fallback() {
+        let state = ::State::new(::JS_STRICT);
+        state.pushnull();
+        state.newuserdatax("Flag", true, |js, flag: &mut bool, name| {
+            match name == "flag" && *flag {
+                true => { js.pushboolean(true); true },
+                false => false
+            }
+        }, |_, _, name| name == "flag", |_, flag, name| {
+            if name == "flag" {
+                *flag = false;
+            }
+            name == "flag"
+        }).unwrap();
+        state.setglobal("obj").unwrap();
+        state.dostring("obj.other = 1; delete obj.flag;").unwrap();
+        state.getglobal("obj").unwrap();
+        state.getproperty(0, "flag").unwrap();
+        state.getproperty(0, "other").unwrap();
+        assert!(state.isundefined(1));
+        assert_eq!(state.tonumber(2).unwrap(), 1.0);
+    }
+
+    #[test]
+    fn setreport_receives_warnings() {
+        use std::sync::{Arc, Mutex};
+        let state = ::State::new(::JS_STRICT);
+        let reports = Arc::new(Mutex::new(Vec::new()));
+        let sink = reports.clone();
+        state.setreport(move |message| sink.lock().unwrap().push(message.to_string()));
+        state.gc(true);
+        assert_eq!(reports.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn as_raw_shares_stack() {
+        let state = ::State::new(::JS_STRICT);
+        let name = ::std::ffi::CString::new("raw").unwrap();
+        unsafe { ::sys::js_pushstring(state.as_raw(), name.as_ptr()) };
+        assert_eq!(state.tostring(0).unwrap(), "raw");
+    }
+
+    #[test]
+    fn from_raw_keeps_host_data() {
+        use std::sync::Arc;
+        let value = Arc::new(());
+        let raw = ::State::new(::JS_STRICT).into_raw();
+        {
+            let state = unsafe { ::State::from_raw(raw) };
+            let value = value.clone();
+            state.newfunction(move |js| {
+                js.pushnumber(Arc::strong_count(&value) as f64);
+            }, "count", 0).unwrap();
+            state.setglobal("count").unwrap();
+        }
+        {
+            let state = unsafe { ::State::from_raw(raw) };
+            state.dostring("var n = count();").unwrap();
+            state.getglobal("n").unwrap();
+            assert_eq!(state.tonumber(-1).unwrap(), 2.0);
+        }
+        unsafe { ::sys::js_freestate(raw) };
+    }
+
+    #[test]
+    fn from_raw_on_foreign_state() {
+        let raw = unsafe { ::sys::js_newstate(None, ::std::ptr::null_mut(), 0) };
+        {
+            let state = unsafe { ::State::from_raw(raw) };
+            state.newfunction(|js| js.pushstring("rust"), "lang", 0).unwrap();
+            state.setglobal("lang").unwrap();
+            state.dostring("var l = lang();").unwrap();
+            state.getglobal("l").unwrap();
+            assert_eq!(state.tostring(-1).unwrap(), "rust");
+        }
+        unsafe { ::sys::js_freestate(raw) };
+    }
+
+    #[test]
+    fn set_userdata_replaces_value() {
+        let state = ::State::new(::JS_STRICT);
+        assert!(state.userdata::<String>().is_none());
+        state.set_userdata("first".to_string());
+        let first = state.userdata::<String>().unwrap();
+        state.set_userdata("second".to_string());
+        state.set_userdata(7u32);
+        assert_eq!(first, "first");
+        assert_eq!(state.userdata::<String>().unwrap(), "second");
+        assert_eq!(*state.userdata::<u32>().unwrap(), 7);
+    }
+
+    #[test]
+    fn clear_userdata_drops_replaced_values() {
+        use std::sync::Arc;
+        let value = Arc::new(());
+        let mut state = ::State::new(::JS_STRICT);
+        state.set_userdata(value.clone());
+        state.set_userdata(value.clone());
+        assert_eq!(Arc::strong_count(&value), 3);
+        state.clear_userdata();
+        assert_eq!(Arc::strong_count(&value), 1);
+        assert!(state.userdata::<Arc<()>>().is_none());
+    }
+
+    #[test]
+    fn scope_drops_closures_at_end() {
+        use std::rc::Rc;
+        let state = ::State::new(::JS_STRICT);
+        let value = Rc::new(());
+        let mut calls = 0;
+        state.scope(|scope| {
+            let shared = value.clone();
+            let calls = &mut calls;
+            scope.newfunction(move |_| {
+                let _ = &shared;
+                *calls += 1;
+            }, "call", 0).unwrap();
+            scope.setglobal("call").unwrap();
+            scope.dostring("call(); call();").unwrap();
+            assert_eq!(Rc::strong_count(&value), 2);
+        });
+        assert_eq!(Rc::strong_count(&value), 1);
+        assert_eq!(calls, 2);
+
+        state.loadstring("after", "call();").unwrap();
+        state.pushundefined();
+        let err = state.call(0).unwrap_err();
+        assert!(err.message.contains("scoped function 'call' called after its scope ended"));
+    }
+
+    #[test]
+    fn new_function_rest_and_len() {
+        let state = ::State::new(::JS_STRICT);
+        state.new_function(|args| {
+            let values: Vec<f64> = args.rest(1)?;
+            args.ret(args.len() as f64 + values.iter().sum::<f64>());
+            Ok(())
+        }, "sum", 1).unwrap();
+        state.setglobal("sum").unwrap();
+        state.dostring("var a = sum(), b = sum('x', 1, 2);").unwrap();
+        state.getglobal("a").unwrap();
+        state.getglobal("b").unwrap();
+        assert_eq!(state.tonumber(0).unwrap(), 1.0);
+        assert_eq!(state.tonumber(1).unwrap(), 6.0);
+    }
+
+    #[test]
+    fn new_function_throws_error() {
+        let state = ::State::new(::JS_STRICT);
+        state.new_function(|args| {
+            match args.get::<Option<bool>>(0)? {
+                Some(true) => Err("failed".into()),
+                _ => Ok(())
+            }
+        }, "fail", 1).unwrap();
+        state.setglobal("fail").unwrap();
+        assert!(state.dostring("fail(); fail(null);").is_ok());
+        state.loadstring("fail.js", "try { fail(2); } catch (e) { var a = e.name; } fail(true);").unwrap();
+        state.pushundefined();
+        assert_eq!(state.call(0).unwrap_err().message, "Error: failed");
+        state.getglobal("a").unwrap();
+        assert_eq!(state.tostring(-1).unwrap(), "TypeError");
+    }
+
+    #[test]
+    fn new_function_error_with_nul_is_thrown() {
+        let state = ::State::new(::JS_STRICT);
+        state.new_function(|args| {
+            let name: String = args.get(0)?;
+            Err(::CallError::Type(format!("unknown name {}", name)))
+        }, "lookup", 1).unwrap();
+        state.setglobal("lookup").unwrap();
+        state.dostring("var result; try { lookup('a\\0b'); } catch (e) { result = e.message; }").unwrap();
+        state.getglobal("result").unwrap();
+        assert_eq!(state.tostring(-1).unwrap(), "unknown name a\\0b");
+    }
+
+    #[test]
+    fn new_function_len_with_length_zero_counts_arguments() {
+        let state = ::State::new(::JS_STRICT);
+        state.new_function(|args| {
+            args.ret(args.len() as f64);
+            Ok(())
+        }, "count", 0).unwrap();
+        state.setglobal("count").unwrap();
+        state.dostring("var a = count(), b = count(undefined);").unwrap();
+        state.getglobal("a").unwrap();
+        state.getglobal("b").unwrap();
+        assert_eq!(state.tonumber(0).unwrap(), 0.0);
+        assert_eq!(state.tonumber(1).unwrap(), 1.0);
+    }
+
+    #[test]
+    fn from_js_integer_out_of_range() {
+        use ::FromJs;
+        let state = ::State::new(::JS_STRICT);
+        state.pushnumber(300.0);
+        state.pushnumber(1.5);
+        assert!(u8::from_js(&state, 0).is_err());
+        assert_eq!(u16::from_js(&state, 0).unwrap(), 300);
+        assert!(i32::from_js(&state, 1).is_err());
+        assert_eq!(f32::from_js(&state, 1).unwrap(), 1.5);
+    }
+
+    #[test]
+    fn from_js_integer_power_of_two_bounds() {
+        use ::FromJs;
+        let state = ::State::new(::JS_STRICT);
+        state.pushnumber(18446744073709551616.0);
+        state.pushnumber(9223372036854775808.0);
+        state.pushnumber(-9223372036854775808.0);
+        state.pushnumber(256.0);
+        assert!(u64::from_js(&state, 0).is_err());
+        assert_eq!(u64::from_js(&state, 1).unwrap(), 1 << 63);
+        assert!(i64::from_js(&state, 1).is_err());
+        assert_eq!(i64::from_js(&state, 2).unwrap(), i64::MIN);
+        assert!(u8::from_js(&state, 3).is_err());
+        assert_eq!(u16::from_js(&state, 3).unwrap(), 256);
+    }
+
+    #[test]
+    fn new_constructor_instances() {
+        let state = ::State::new(::JS_STRICT);
+        state.new_constructor(|_| {}, |js| {
+            js.pushboolean(true);
+            js.setproperty(0, "built").unwrap();
+        }, "Thing", 0).unwrap();
+        state.setglobal("Thing").unwrap();
+        state.dostring("Object.create = null; var t = new Thing();
+            var ok = t instanceof Thing && t.built && t.constructor === Thing;").unwrap();
+        state.getglobal("ok").unwrap();
+        assert!(state.toboolean(-1).unwrap());
+    }
+
+    #[test]
+    fn new_constructor_returning_other_object() {
+        let state = ::State::new(::JS_STRICT);
+        state.new_constructor(|_| {}, |js| js.newarray(), "Other", 0).unwrap();
+        state.setglobal("Other").unwrap();
+        state.dostring("var o = new Other(), ok = Array.isArray(o) && !(o instanceof Other);").unwrap();
+        state.getglobal("ok").unwrap();
+        assert!(state.toboolean(-1).unwrap());
+    }
+
+    struct Squares {
+        reads: u32,
+    }
+
+    impl ::DynamicObject for Squares {
+        fn has(self: &Squares, name: &str) -> bool {
+            name.parse::<u32>().is_ok()
+        }
+
+        fn get(self: &mut Squares, state: &::State, name: &str) {
+            self.reads += 1;
+            let n: u32 = name.parse().unwrap();
+            state.pushnumber((n * n) as f64);
+        }
+    }
+
+    #[test]
+    fn push_dynamic_falls_back_to_properties() {
+        let state = ::State::new(::JS_STRICT);
+        state.push_dynamic(Squares { reads: 0 }).unwrap();
+        state.setglobal("squares").unwrap();
+        state.dostring("squares.name = 'squares';
+            var result = [squares[3], squares.name, 'x' in squares, 4 in squares].join();").unwrap();
+        state.getglobal("result").unwrap();
+        assert_eq!(state.tostring(-1).unwrap(), "9,squares,false,true");
+
+        state.getglobal("squares").unwrap();
+        assert_eq!(state.with_dynamic(-1, |squares: &mut Squares| squares.reads).unwrap(), 2);
+    }
+
+    #[test]
+    fn push_view_of_slice_is_read_only() {
+        use std::sync::Arc;
+        let names: Arc<[&'static str]> = Arc::from(vec!["a", "b", "c"]);
+        let state = ::State::new(::StateFlags{bits: 0});
+        state.push_view(&names).unwrap();
+        state.setglobal("names").unwrap();
+        state.dostring("names[0] = 'z'; names.extra = 1; delete names[1];
+            var result = names.slice(1).join() + ',' + names.length + ',' + names.extra + ',' + names['01'];").unwrap();
+        state.getglobal("result").unwrap();
+        assert_eq!(state.tostring(-1).unwrap(), "b,c,3,undefined,undefined");
+    }
+
+    #[test]
+    fn push_view_write_in_strict_mode_throws() {
+        use std::sync::Arc;
+        let values = Arc::new(vec![1, 2]);
+        let state = ::State::new(::JS_STRICT);
+        state.push_view(&values).unwrap();
+        state.setglobal("values").unwrap();
+        state.dostring("var write, del;
+            try { values[0] = 3; } catch (e) { write = e.name; }
+            try { delete values[1]; } catch (e) { del = e.name; }
+            var result = [write, del, values[0], values[1]].join();").unwrap();
+        state.getglobal("result").unwrap();
+        assert_eq!(state.tostring(-1).unwrap(), "TypeError,TypeError,1,2");
+    }
+
+    #[test]
+    fn push_view_of_map() {
+        use std::collections::HashMap;
+        use std::sync::Arc;
+        let mut limits = HashMap::new();
+        limits.insert("cpu".to_string(), 4);
+        limits.insert("length".to_string(), 2);
+        let limits = Arc::new(limits);
+        let state = ::State::new(::JS_STRICT);
+        state.push_view(&limits).unwrap();
+        state.setglobal("limits").unwrap();
+        state.dostring("var result = [limits.cpu, limits.length, 'memory' in limits].join();").unwrap();
+        state.getglobal("result").unwrap();
+        assert_eq!(state.tostring(-1).unwrap(), "4,2,false");
+        drop(state);
+        assert_eq!(Arc::strong_count(&limits), 1);
+    }
 }
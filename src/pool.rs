@@ -0,0 +1,318 @@
+//! Pool of pre-initialized States
+
+use std::ops::Deref;
+use std::sync::{Arc, Mutex, Condvar};
+use std::time::{Duration, Instant};
+
+use {State, StateFlags};
+
+/// Registry entry holding the function which resets globals
+static RESET_REGISTRY_NAME: &str = "__RustStatePoolReset__";
+
+/// Script returning a function which captures the current global
+/// names and returns a function removing globals added afterwards
+static RESET_SOURCE: &str = "
+(function (global) {
+    var getOwnPropertyNames = Object.getOwnPropertyNames;
+    var keep = {}, names = getOwnPropertyNames(global), i;
+    for (i = 0; i < names.length; ++i)
+        keep[names[i]] = true;
+    return function () {
+        var names = getOwnPropertyNames(global), i, n = 0;
+        for (i = 0; i < names.length; ++i) {
+            if (keep[names[i]] !== true) {
+                try { delete global[names[i]]; } catch (e) {}
+                try { if (names[i] in global) global[names[i]] = undefined; } catch (e) {}
+                ++n;
+            }
+        }
+        return n;
+    };
+})";
+
+type InitFn = Box<dyn Fn(&State) -> Result<(), String> + Send + Sync>;
+
+/// Statistics of a StatePool
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct PoolMetrics {
+    /// Number of States owned by the pool, available or in use
+    pub size: usize,
+    /// Number of States currently available
+    pub available: usize,
+    /// Total number of States handed out
+    pub acquired: u64,
+    /// Number of times a caller had to wait for an available State
+    pub waits: u64,
+    /// Number of times a State was reset when returned
+    pub resets: u64,
+    /// Number of States which failed to reset and were replaced
+    pub replaced: u64,
+}
+
+struct PoolState {
+    states: Vec<State>,
+    metrics: PoolMetrics,
+}
+
+struct PoolInner {
+    flags: StateFlags,
+    init: InitFn,
+    reset: bool,
+    pool: Mutex<PoolState>,
+    available: Condvar,
+}
+
+/// Pool of pre-initialized States
+///
+/// All States are created up front and initialized by running the
+/// init closure, typically loading a prelude. A State is handed
+/// out with exclusive access through a StateGuard and returned to
+/// the pool when the guard is dropped.
+///
+/// MuJS can not serialize compiled scripts, so a large prelude can
+/// not be loaded without parsing it. Loading it in init pays that
+/// cost once per State when the pool is created rather than each
+/// time a State is needed.
+///
+/// When constructed with StatePool::with_reset() a returned State
+/// is cleaned up before being handed out again. Its stack is
+/// cleared, globals added since init are deleted, or set to
+/// undefined if they can not be deleted such as globals declared
+/// with `var`, and the garbage collector is run. Changes to globals
+/// which existed after init are kept. Likewise values attached with
+/// State::set_userdata() since init are dropped, as are values
+/// replaced since then.
+///
+/// A StatePool can be cloned and shared between threads, each
+/// clone refers to the same set of States.
+///
+/// # Examples
+///
+/// ```
+/// use mujs;
+///
+/// let pool = mujs::StatePool::with_reset(2, mujs::JS_STRICT, |state| {
+///     state.dostring("function greet(name) { return 'Hello ' + name; }")
+/// }).unwrap();
+///
+/// {
+///     let state = pool.get();
+///     state.dostring("var leaked = 1;").unwrap();
+///     state.getglobal("greet").unwrap();
+///     state.pushundefined();
+///     state.pushstring("World");
+///     state.call(1).unwrap();
+///     assert_eq!(state.tostring(-1).unwrap(), "Hello World");
+/// }
+///
+/// assert_eq!(pool.metrics().available, 2);
+/// assert_eq!(pool.metrics().resets, 1);
+/// ```
+#[derive(Clone)]
+pub struct StatePool {
+    inner: Arc<PoolInner>,
+}
+
+/// Exclusive access to a State of a StatePool
+///
+/// Dereferences to the State and returns it to the pool on drop.
+pub struct StateGuard {
+    inner: Arc<PoolInner>,
+    state: Option<State>,
+}
+
+impl PoolInner {
+
+    fn create(self: &PoolInner) -> Result<State, String> {
+        let state = State::new(self.flags);
+        (self.init)(&state)?;
+        state.pop(state.gettop());
+
+        if self.reset {
+            state.loadstring("[pool]", RESET_SOURCE)?;
+            state.pushundefined();
+            state.call(0)?;
+            state.pushundefined();
+            state.pushglobal();
+            state.call(1)?;
+            state._setregistry(RESET_REGISTRY_NAME)?;
+            state.pop(state.gettop());
+            state._snapshot_userdata();
+        }
+
+        Ok(state)
+    }
+
+    fn reset(self: &PoolInner, state: &mut State) -> Result<(), String> {
+        state.pop(state.gettop());
+        state._getregistry(RESET_REGISTRY_NAME)?;
+        state.pushundefined();
+        state.call(0)?;
+        state.pop(state.gettop());
+        state._restore_userdata();
+        state.gc(false);
+        Ok(())
+    }
+
+    fn release(self: &PoolInner, mut state: State) {
+        let (state, replaced) = match self.reset {
+            false => {
+                state.pop(state.gettop());
+                (Some(state), false)
+            },
+            true => match self.reset(&mut state) {
+                Ok(()) => (Some(state), false),
+                Err(_) => {
+                    drop(state);
+                    (self.create().ok(), true)
+                }
+            }
+        };
+
+        let mut pool = self.pool.lock().unwrap();
+        if self.reset {
+            pool.metrics.resets += 1;
+        }
+        if replaced {
+            pool.metrics.replaced += 1;
+        }
+        match state {
+            Some(state) => {
+                pool.states.push(state);
+                pool.metrics.available = pool.states.len();
+            },
+            None => {
+                pool.metrics.size -= 1;
+            }
+        }
+        self.available.notify_one();
+    }
+}
+
+impl StatePool {
+
+    /// Constructs a pool of size States initialized by init
+    ///
+    /// Returns the first error of init if any State failed to
+    /// initialize.
+    pub fn new<F>(size: usize, flags: StateFlags, init: F) -> Result<StatePool, String>
+        where F: Fn(&State) -> Result<(), String> + Send + Sync + 'static
+    {
+        StatePool::create(size, flags, Box::new(init), false)
+    }
+
+    /// Constructs a pool of size States initialized by init which
+    /// are reset when returned to the pool
+    pub fn with_reset<F>(size: usize, flags: StateFlags, init: F) -> Result<StatePool, String>
+        where F: Fn(&State) -> Result<(), String> + Send + Sync + 'static
+    {
+        StatePool::create(size, flags, Box::new(init), true)
+    }
+
+    fn create(size: usize, flags: StateFlags, init: InitFn,
+              reset: bool) -> Result<StatePool, String> {
+        let inner = PoolInner {
+            flags,
+            init,
+            reset,
+            pool: Mutex::new(PoolState {
+                states: Vec::with_capacity(size),
+                metrics: PoolMetrics::default(),
+            }),
+            available: Condvar::new(),
+        };
+
+        {
+            let mut pool = inner.pool.lock().unwrap();
+            for _ in 0..size {
+                let state = inner.create()?;
+                pool.states.push(state);
+            }
+            pool.metrics.size = size;
+            pool.metrics.available = size;
+        }
+
+        Ok(StatePool {
+            inner: Arc::new(inner),
+        })
+    }
+
+    fn acquire(self: &StatePool, timeout: Option<Duration>) -> Option<StateGuard> {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        let mut pool = self.inner.pool.lock().unwrap();
+        let mut waited = false;
+
+        loop {
+            if let Some(state) = pool.states.pop() {
+                pool.metrics.available = pool.states.len();
+                pool.metrics.acquired += 1;
+                if waited {
+                    pool.metrics.waits += 1;
+                }
+                return Some(StateGuard {
+                    inner: self.inner.clone(),
+                    state: Some(state),
+                });
+            }
+
+            if pool.metrics.size == 0 {
+                return None;
+            }
+
+            waited = true;
+            pool = match deadline {
+                None => self.inner.available.wait(pool).unwrap(),
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        return None;
+                    }
+                    self.inner.available.wait_timeout(pool, deadline - now).unwrap().0
+                }
+            };
+        }
+    }
+
+    /// Get a State from the pool, waiting until one is available
+    ///
+    /// # Panics
+    ///
+    /// Panics if the pool is empty, which happens if it was
+    /// constructed with size zero or all States failed to be
+    /// replaced after a failing reset.
+    pub fn get(self: &StatePool) -> StateGuard {
+        self.acquire(None).expect("StatePool has no States")
+    }
+
+    /// Get a State from the pool if one is available
+    pub fn try_get(self: &StatePool) -> Option<StateGuard> {
+        self.acquire(Some(Duration::from_secs(0)))
+    }
+
+    /// Get a State from the pool, waiting at most timeout for one
+    /// to become available
+    pub fn get_timeout(self: &StatePool, timeout: Duration) -> Option<StateGuard> {
+        self.acquire(Some(timeout))
+    }
+
+    /// Get current statistics of the pool
+    pub fn metrics(self: &StatePool) -> PoolMetrics {
+        self.inner.pool.lock().unwrap().metrics
+    }
+}
+
+impl Deref for StateGuard {
+    type Target = State;
+
+    fn deref(self: &StateGuard) -> &State {
+        self.state.as_ref().unwrap()
+    }
+}
+
+impl Drop for StateGuard {
+    fn drop(self: &mut StateGuard) {
+        if let Some(state) = self.state.take() {
+            self.inner.release(state);
+        }
+    }
+}
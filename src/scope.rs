@@ -0,0 +1,124 @@
+//! Host functions borrowing from the stack frame of the caller
+
+use std::cell::{Cell, RefCell};
+use std::marker::PhantomData;
+use std::ops::Deref;
+use std::sync::Arc;
+
+use State;
+
+type ScopedFn = *mut (dyn FnMut(&State) + 'static);
+
+/// Closure of a scoped function, None once the scope has ended
+struct Slot {
+    func: Cell<Option<ScopedFn>>,
+}
+
+// The closure is only called and dropped by the thread using the
+// State which the scope borrows, the slot merely travels along with
+// the function object.
+unsafe impl Send for Slot {}
+unsafe impl Sync for Slot {}
+
+/// Registers functions which may borrow data living for `'env`
+///
+/// Created by State::scope(), dereferences to the State.
+pub struct Scope<'env> {
+    state: &'env State,
+    slots: RefCell<Vec<Arc<Slot>>>,
+    _env: PhantomData<Cell<&'env mut ()>>,
+}
+
+impl<'env> Scope<'env> {
+
+    /// push a function object wrapping a closure borrowing from the
+    /// enclosing stack frame
+    ///
+    /// Behaves as State::newfunction() until the scope ends, after
+    /// which calling the function throws an Error.
+    pub fn newfunction<F>(self: &Scope<'env>, func: F, name: &str, length: i32) -> Result<(), String>
+        where F: FnMut(&State) + 'env
+    {
+        let func: Box<dyn FnMut(&State) + 'env> = Box::new(func);
+        // The lifetime is erased for storage, the closure is dropped
+        // when the scope ends and never called afterwards.
+        let func: ScopedFn = unsafe { ::std::mem::transmute(Box::into_raw(func)) };
+        let slot = Arc::new(Slot { func: Cell::new(Some(func)) });
+        self.slots.borrow_mut().push(slot.clone());
+
+        let function = name.to_string();
+        self.state.newfunction(move |js| {
+            match slot.func.get() {
+                Some(func) => unsafe { (*func)(js) },
+                None => {
+                    // Throwing does not return, drop the message first
+                    let message = format!("scoped function '{}' called after its scope ended",
+                                          function);
+                    let _ = js.newerror(&message);
+                    drop(message);
+                    js.throw();
+                }
+            }
+        }, name, length)
+    }
+}
+
+impl<'env> Deref for Scope<'env> {
+    type Target = State;
+
+    fn deref(self: &Scope<'env>) -> &State {
+        self.state
+    }
+}
+
+impl<'env> Drop for Scope<'env> {
+    fn drop(self: &mut Scope<'env>) {
+        for slot in self.slots.borrow_mut().drain(..) {
+            if let Some(func) = slot.func.take() {
+                unsafe { drop(Box::from_raw(func)) };
+            }
+        }
+    }
+}
+
+impl State {
+
+    /// Run func with a Scope for registering functions which borrow
+    /// from the enclosing stack frame
+    ///
+    /// Unlike State::newfunction() the closures need not be
+    /// `'static` or `Send`. They are dropped when func returns and
+    /// calling the function objects afterwards, for example when
+    /// kept in a global, throws an Error.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use mujs;
+    ///
+    /// let state = mujs::State::new(mujs::JS_STRICT);
+    /// let mut lines = Vec::new();
+    ///
+    /// state.scope(|scope| {
+    ///     scope.newfunction(|js| {
+    ///         lines.push(js.tostring(1).unwrap());
+    ///     }, "emit", 1).unwrap();
+    ///     scope.setglobal("emit").unwrap();
+    ///
+    ///     scope.dostring("emit('one'); emit('two');").unwrap();
+    /// });
+    ///
+    /// assert_eq!(lines, vec!["one", "two"]);
+    /// assert!(state.dostring("emit('three');").is_err());
+    /// ```
+    pub fn scope<'env, R, F>(self: &'env State, func: F) -> R
+        where F: FnOnce(&Scope<'env>) -> R
+    {
+        let scope = Scope {
+            state: self,
+            slots: RefCell::new(Vec::new()),
+            _env: PhantomData,
+        };
+        func(&scope)
+    }
+}
@@ -0,0 +1,364 @@
+//! Async host functions returning thenable objects
+
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::task::{Context, Poll, Wake, Waker};
+
+use {State, ToJs};
+
+/// Registry entry holding the thenable runtime object
+static RUNTIME_REGISTRY_NAME: &str = "__RustAsyncRuntime__";
+
+/// Script evaluating to the thenable runtime object
+///
+/// Thenables follow the Promises/A+ resolution procedure. Callbacks
+/// are never invoked synchronously from then() but queued and run
+/// when the queue is drained by State::run_until_idle().
+static RUNTIME_SOURCE: &str = "
+(function () {
+    var queue = [];
+    function Thenable() {
+        this.state = 0;
+        this.value = undefined;
+        this.handlers = [];
+    }
+    function schedule(p, h) {
+        queue.push(function () {
+            var cb = p.state === 1 ? h.onFulfilled : h.onRejected;
+            if (typeof cb !== 'function') {
+                if (p.state === 1)
+                    resolve(h.next, p.value);
+                else
+                    settle(h.next, 2, p.value);
+                return;
+            }
+            try {
+                resolve(h.next, cb(p.value));
+            } catch (e) {
+                settle(h.next, 2, e);
+            }
+        });
+    }
+    function settle(p, state, value) {
+        var handlers = p.handlers, i;
+        if (p.state !== 0)
+            return;
+        p.state = state;
+        p.value = value;
+        p.handlers = null;
+        for (i = 0; i < handlers.length; ++i)
+            schedule(p, handlers[i]);
+    }
+    function resolve(p, value) {
+        var then, done = false;
+        if (value === p)
+            return settle(p, 2, new TypeError('thenable resolved with itself'));
+        if (value !== null && (typeof value === 'object' || typeof value === 'function')) {
+            try {
+                then = value.then;
+            } catch (e) {
+                return settle(p, 2, e);
+            }
+            if (typeof then === 'function') {
+                try {
+                    then.call(value,
+                              function (v) { if (!done) { done = true; resolve(p, v); } },
+                              function (r) { if (!done) { done = true; settle(p, 2, r); } });
+                } catch (e) {
+                    if (!done) { done = true; settle(p, 2, e); }
+                }
+                return;
+            }
+        }
+        settle(p, 1, value);
+    }
+    Thenable.prototype.then = function (onFulfilled, onRejected) {
+        var next = new Thenable();
+        var h = { onFulfilled: onFulfilled, onRejected: onRejected, next: next };
+        if (this.state === 0)
+            this.handlers.push(h);
+        else
+            schedule(this, h);
+        return next;
+    };
+    Thenable.prototype['catch'] = function (onRejected) {
+        return this.then(undefined, onRejected);
+    };
+    return {
+        create: function () { return new Thenable(); },
+        resolve: function (p, value) { resolve(p, value); },
+        reject: function (p, reason) { settle(p, 2, reason); },
+        enqueue: function (fn) { queue.push(fn); },
+        drain: function () {
+            var n = 0;
+            while (queue.length > 0) {
+                queue.shift()();
+                ++n;
+            }
+            return n;
+        }
+    };
+})()";
+
+type TaskOutput = Result<Box<dyn ToJs + Send>, String>;
+
+struct TaskWaker {
+    woken: AtomicBool,
+}
+
+impl Wake for TaskWaker {
+    fn wake(self: Arc<TaskWaker>) {
+        self.woken.store(true, Ordering::SeqCst);
+    }
+
+    fn wake_by_ref(self: &Arc<TaskWaker>) {
+        self.woken.store(true, Ordering::SeqCst);
+    }
+}
+
+/// Wraps a future boxing its successful output
+struct BoxOutput<T> {
+    future: Pin<Box<dyn Future<Output = Result<T, String>> + Send>>,
+}
+
+impl<T> Future for BoxOutput<T> where T: ToJs + Send + 'static {
+    type Output = TaskOutput;
+
+    fn poll(mut self: Pin<&mut BoxOutput<T>>, cx: &mut Context) -> Poll<TaskOutput> {
+        match self.future.as_mut().poll(cx) {
+            Poll::Pending => Poll::Pending,
+            Poll::Ready(res) => Poll::Ready(res.map(|value| Box::new(value) as Box<dyn ToJs + Send>))
+        }
+    }
+}
+
+struct Task {
+    registry_name: String,
+    future: Pin<Box<dyn Future<Output = TaskOutput> + Send>>,
+    waker: Arc<TaskWaker>,
+}
+
+/// Pending async host function calls of a State
+pub struct Tasks {
+    next_id: u64,
+    tasks: Vec<Task>,
+}
+
+impl Tasks {
+    pub fn new() -> Tasks {
+        Tasks {
+            next_id: 0,
+            tasks: Vec::new(),
+        }
+    }
+}
+
+impl State {
+
+    /// Push a method of the thenable runtime object followed by the
+    /// runtime object as this, creating the runtime on first use
+    fn _async_runtime_method(self: &State, method: &str) -> Result<(), String> {
+        self._getregistry(RUNTIME_REGISTRY_NAME)?;
+        if self.isundefined(-1) {
+            self.pop(1);
+            self.loadstring("[async]", RUNTIME_SOURCE)?;
+            self.pushundefined();
+            self.call(0)?;
+            self.copy(-1);
+            self._setregistry(RUNTIME_REGISTRY_NAME)?;
+        }
+        self.getproperty(-1, method)?;
+        self.rot(2);
+        Ok(())
+    }
+
+    /// Pop the function on top of stack and queue it to run with the
+    /// thenable callbacks
+    pub(crate) fn _enqueue_job(self: &State) -> Result<(), String> {
+        self._async_runtime_method("enqueue")?;
+        self.rot(3);
+        self.rot(3);
+        self.call(1)?;
+        self.pop(1);
+        Ok(())
+    }
+
+    /// push a function object wrapping a rust closure returning a future
+    ///
+    /// When called from script the closure is invoked with the
+    /// arguments on the stack, as with State::newfunction(), and the
+    /// returned future is queued while a thenable object is
+    /// returned to the script. The thenable is fulfilled with the
+    /// output of the future, or rejected with an Error carrying the
+    /// message of a failing future, by State::run_until_idle().
+    ///
+    /// Futures are polled without an async runtime, they are woken
+    /// through the standard Waker and must make progress on their
+    /// own, for example when completed from another thread.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use mujs;
+    /// use std::future;
+    ///
+    /// let state = mujs::State::new(mujs::JS_STRICT);
+    ///
+    /// state.new_async_function(|js| {
+    ///     let n = js.tonumber(1).unwrap();
+    ///     future::ready(Ok(n * 2.0))
+    /// }, "double", 1).unwrap();
+    /// state.setglobal("double").unwrap();
+    ///
+    /// state.dostring("var result; double(21).then(function (v) { result = v; });").unwrap();
+    /// state.run_until_idle().unwrap();
+    ///
+    /// state.getglobal("result").unwrap();
+    /// assert_eq!(state.tonumber(-1).unwrap(), 42.0);
+    /// ```
+    pub fn new_async_function<F, Fut, T>(self: &State, mut func: F, name: &str,
+                                         length: i32) -> Result<(), String>
+        where F: FnMut(&State) -> Fut + Send + 'static,
+              Fut: Future<Output = Result<T, String>> + Send + 'static,
+              T: ToJs + Send + 'static
+    {
+        self.newfunction(move |js| {
+            let future = BoxOutput {
+                future: Box::pin(func(js)),
+            };
+
+            // Throwing does not return, the future is dropped first
+            // so that it is not leaked.
+            if js._async_runtime_method("create").and_then(|_| js.call(0).map_err(String::from)).is_err() {
+                drop(future);
+                let _ = js.error("Failed to create thenable");
+                return;
+            }
+
+            let registry_name = {
+                let mut tasks = js._internal().tasks.borrow_mut();
+                tasks.next_id += 1;
+                format!("__RustAsyncTask{}__", tasks.next_id)
+            };
+
+            js.copy(-1);
+            if js._setregistry(&registry_name).is_err() {
+                drop(registry_name);
+                drop(future);
+                let _ = js.error("Failed to register thenable");
+                return;
+            }
+
+            js._internal().tasks.borrow_mut().tasks.push(Task {
+                registry_name,
+                future: Box::pin(future),
+                waker: Arc::new(TaskWaker { woken: AtomicBool::new(true) }),
+            });
+        }, name, length)
+    }
+
+    /// Fulfill or reject the thenable of a completed task
+    fn _async_settle(self: &State, registry_name: &str, output: TaskOutput) -> Result<(), String> {
+        let top = self.gettop();
+        let res = self._async_settle_call(registry_name, output);
+        let extra = self.gettop() - top;
+        if extra > 0 {
+            self.pop(extra);
+        }
+        // The thenable is released even when settling failed
+        let del = self._delregistry(registry_name);
+        res.and(del)
+    }
+
+    fn _async_settle_call(self: &State, registry_name: &str, output: TaskOutput) -> Result<(), String> {
+        match output {
+            Ok(ref value) => {
+                self._async_runtime_method("resolve")?;
+                self._getregistry(registry_name)?;
+                value.push_js(self);
+            },
+            Err(ref message) => {
+                self._async_runtime_method("reject")?;
+                self._getregistry(registry_name)?;
+                self.newerror(message)?;
+            }
+        }
+        self.call(2)?;
+        self.pop(1);
+        Ok(())
+    }
+
+    /// Drive pending async host functions and thenable callbacks
+    ///
+    /// Polls all woken futures created by functions registered with
+    /// State::new_async_function(), settles the thenables of
+    /// completed futures and runs the queued thenable callbacks,
+    /// repeating until no more progress can be made.
+    ///
+    /// Returns the number of futures still pending.
+    pub fn run_until_idle(self: &State) -> Result<usize, String> {
+        loop {
+            let mut progress = false;
+
+            // Take the tasks out so that new tasks can be queued by
+            // callbacks while polling.
+            let tasks = ::std::mem::take(&mut self._internal().tasks.borrow_mut().tasks);
+
+            let mut pending = Vec::with_capacity(tasks.len());
+            let mut completed = Vec::new();
+            for mut task in tasks {
+                if !task.waker.woken.swap(false, Ordering::SeqCst) {
+                    pending.push(task);
+                    continue;
+                }
+
+                let waker = Waker::from(task.waker.clone());
+                let mut cx = Context::from_waker(&waker);
+                match task.future.as_mut().poll(&mut cx) {
+                    Poll::Pending => pending.push(task),
+                    Poll::Ready(output) => completed.push((task.registry_name, output))
+                }
+            }
+
+            {
+                let tasks = &mut self._internal().tasks.borrow_mut().tasks;
+                pending.append(tasks);
+                *tasks = pending;
+            }
+
+            // Settle every completed task before reporting the first
+            // error, the others would never settle otherwise.
+            let mut error = None;
+            for (registry_name, output) in completed {
+                if let Err(err) = self._async_settle(&registry_name, output) {
+                    error = error.or(Some(err));
+                }
+                progress = true;
+            }
+            if let Some(err) = error {
+                return Err(err);
+            }
+
+            self._getregistry(RUNTIME_REGISTRY_NAME)?;
+            let initialized = !self.isundefined(-1);
+            self.pop(1);
+            if initialized {
+                self._async_runtime_method("drain")?;
+                self.call(0)?;
+                if self.tonumber(-1)? > 0.0 {
+                    progress = true;
+                }
+                self.pop(1);
+            }
+
+            if !progress {
+                let tasks = &self._internal().tasks.borrow().tasks;
+                if !tasks.iter().any(|task| task.waker.woken.load(Ordering::SeqCst)) {
+                    return Ok(tasks.len());
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,346 @@
+//! Userdata objects owning Rust values
+
+use std::any::Any;
+use std::ffi::{CStr, CString};
+use std::ptr;
+
+use libc::{c_char, c_int, c_void};
+use sys::js_State;
+
+use {State, InternalState, ToCString};
+use utf;
+
+type Callback = Box<dyn FnMut(&State, &mut (dyn Any + Send), &str) -> bool + Send>;
+
+/// Prefix of the MuJS tags of userdata objects created here, which
+/// keeps user tags apart from the tags of internal userdata holding
+/// other types
+static TAG_PREFIX: &str = "__RustUserdata:";
+
+/// Registry entry holding the function running property callbacks
+static HOOK_REGISTRY_NAME: &[u8] = b"__RustUserdataHook__\0";
+
+/// MuJS tag of userdata objects with tag
+fn userdata_tag(tag: &str) -> Result<CString, String> {
+    format!("{}{}", TAG_PREFIX, tag).to_cstring()
+}
+
+/// Value and property callbacks owned by a userdata object
+///
+/// Freed by the finalizer when the object is garbage collected. The
+/// tag is kept here since MuJS stores the pointer and not a copy.
+struct Userdata {
+    tag: CString,
+    internal: *mut InternalState,
+    value: Box<dyn Any + Send>,
+    has: Option<Callback>,
+    put: Option<Callback>,
+    delete: Option<Callback>,
+    borrowed: bool,
+}
+
+/// Property callback waiting to be run by the hook function
+pub(crate) struct HookCall {
+    func: *mut Callback,
+    value: *mut (dyn Any + Send),
+    name: *const str,
+    handled: bool,
+}
+
+/// Property hooks of a userdata object
+#[derive(Clone, Copy, PartialEq)]
+enum Hook {
+    Has,
+    Put,
+    Delete,
+}
+
+/// Resets the borrowed flag of a userdata object when dropped, also
+/// when the borrowing closure panics
+struct Borrow(*mut Userdata);
+
+impl Drop for Borrow {
+    fn drop(self: &mut Borrow) {
+        unsafe { (*self.0).borrowed = false };
+    }
+}
+
+/// Wrap a typed property callback into one taking the boxed value
+fn callback<T, F>(mut func: F) -> Callback
+    where T: Any + Send,
+          F: FnMut(&State, &mut T, &str) -> bool + Send + 'static
+{
+    Box::new(move |js, value: &mut (dyn Any + Send), name| {
+        match value.downcast_mut::<T>() {
+            Some(value) => func(js, value, name),
+            None => false
+        }
+    })
+}
+
+/// Run the property callback of hook, unless the value is already
+/// borrowed by a callback or State::with_userdata()
+///
+/// The callback runs in a protected call of the hook function. An
+/// exception it throws is caught, and thrown again once the value is
+/// no longer borrowed and the property name has been dropped.
+fn _dispatch(data: *mut c_void, name: *const c_char, hook: Hook) -> c_int {
+    let userdata = unsafe { &mut *(data as *mut Userdata) };
+    if userdata.borrowed {
+        return 0;
+    }
+    let state = unsafe { State::_from_internal(userdata.internal) };
+    let value: *mut (dyn Any + Send) = &mut *userdata.value;
+    let func = match hook {
+        Hook::Has => userdata.has.as_mut(),
+        Hook::Put => userdata.put.as_mut(),
+        Hook::Delete => userdata.delete.as_mut()
+    };
+    let func: *mut Callback = match func {
+        Some(func) => func,
+        None => return 0
+    };
+
+    let name = utf::decode_str_lossy(unsafe { CStr::from_ptr(name) }.to_bytes());
+    let mut call = HookCall {
+        func,
+        value,
+        name: &*name,
+        handled: false,
+    };
+
+    // The value being assigned is passed on as the only argument so
+    // that the put callback still finds it on top of stack.
+    let args = match hook {
+        Hook::Put => 1,
+        _ => 0
+    };
+    state._push_hook_function();
+    state.pushundefined();
+    if args > 0 {
+        state.copy(-3);
+    }
+    state._internal().hook_call.set(&mut call);
+    userdata.borrowed = true;
+    let failed = unsafe { ::js_pcall((*state.ptr).state, args) } != 0;
+    userdata.borrowed = false;
+
+    let error = state._internal().hook_error.borrow_mut().take();
+    if failed || error.is_some() {
+        drop(name);
+        if let Some(message) = error {
+            state.pop(1);
+            let pushed = state.newtypeerror(&message).is_ok();
+            drop(message);
+            if !pushed {
+                state.pushundefined();
+            }
+        }
+        state.throw();
+    }
+
+    // The has callback pushed the property value as result
+    if hook != Hook::Has || !call.handled {
+        state.pop(1);
+    }
+    call.handled as c_int
+}
+
+extern "C" fn _userdata_has(_: *mut js_State, data: *mut c_void, name: *const c_char) -> c_int {
+    _dispatch(data, name, Hook::Has)
+}
+
+extern "C" fn _userdata_put(_: *mut js_State, data: *mut c_void, name: *const c_char) -> c_int {
+    _dispatch(data, name, Hook::Put)
+}
+
+extern "C" fn _userdata_delete(_: *mut js_State, data: *mut c_void, name: *const c_char) -> c_int {
+    _dispatch(data, name, Hook::Delete)
+}
+
+extern "C" fn _userdata_finalize(_: *mut js_State, data: *mut c_void) {
+    unsafe { drop(Box::from_raw(data as *mut Userdata)) };
+}
+
+impl State {
+
+    /// Throw a TypeError once the running userdata property hook has
+    /// returned
+    pub(crate) fn _hook_typeerror(self: &State, message: String) {
+        *self._internal().hook_error.borrow_mut() = Some(message);
+    }
+
+    /// Test if the State was created with JS_STRICT
+    pub(crate) fn _is_strict(self: &State) -> bool {
+        self._internal().strict.get()
+    }
+
+    /// Push the function running the pending property callback,
+    /// creating it on first use
+    fn _push_hook_function(self: &State) {
+        let js = unsafe { (*self.ptr).state };
+        unsafe { ::js_getregistry(js, HOOK_REGISTRY_NAME.as_ptr() as *const c_char) };
+        if !self.isundefined(-1) {
+            return;
+        }
+        self.pop(1);
+        let _ = self.newfunction(|js| {
+            let call = js._internal().hook_call.replace(ptr::null_mut());
+            if let Some(call) = unsafe { call.as_mut() } {
+                let func = unsafe { &mut *call.func };
+                call.handled = func(js, unsafe { &mut *call.value }, unsafe { &*call.name });
+            }
+        }, "hook", 0);
+        self.copy(-1);
+        unsafe { ::js_setregistry(js, HOOK_REGISTRY_NAME.as_ptr() as *const c_char) };
+    }
+
+    fn _newuserdata(self: &State, userdata: Userdata) {
+        let has = userdata.has.is_some();
+        let put = userdata.put.is_some();
+        let delete = userdata.delete.is_some();
+        let tag = userdata.tag.as_ptr();
+        let data = Box::into_raw(Box::new(userdata)) as *mut c_void;
+        unsafe {
+            ::js_newuserdatax((*self.ptr).state, tag, data,
+                              if has { Some(_userdata_has) } else { None },
+                              if put { Some(_userdata_put) } else { None },
+                              if delete { Some(_userdata_delete) } else { None },
+                              Some(_userdata_finalize));
+        }
+    }
+
+    /// Pop the prototype object on top of stack and push a userdata
+    /// object owning value
+    ///
+    /// Push null instead of a prototype to use Object.prototype. The
+    /// value is dropped when the object is garbage collected or the
+    /// State is freed, and can be accessed with
+    /// State::with_userdata() using the same tag.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use mujs;
+    ///
+    /// let state = mujs::State::new(mujs::JS_STRICT);
+    ///
+    /// state.pushnull();
+    /// state.newuserdata("Counter", 0u32).unwrap();
+    /// state.with_userdata(0, "Counter", |count: &mut u32| *count += 1).unwrap();
+    ///
+    /// assert_eq!(state.with_userdata(0, "Counter", |count: &mut u32| *count).unwrap(), 1);
+    /// ```
+    pub fn newuserdata<T>(self: &State, tag: &str, value: T) -> Result<(), String>
+        where T: Any + Send
+    {
+        self._newuserdata(Userdata {
+            tag: userdata_tag(tag)?,
+            internal: self.ptr,
+            value: Box::new(value),
+            has: None,
+            put: None,
+            delete: None,
+            borrowed: false,
+        });
+        Ok(())
+    }
+
+    /// Pop the prototype object on top of stack and push a userdata
+    /// object owning value with custom property access
+    ///
+    /// Each callback receives the property name and returns true if
+    /// it handled the access, otherwise the property is looked up on
+    /// the object as usual. The has callback must push the property
+    /// value when returning true, the put callback finds the value
+    /// being assigned on top of stack and must leave it there.
+    ///
+    /// The callbacks run in a protected call, an exception thrown by
+    /// a callback, for example by State::tostring() of a value with a
+    /// throwing toString(), is passed on to the script accessing the
+    /// property.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use mujs;
+    /// use std::collections::HashMap;
+    ///
+    /// let state = mujs::State::new(mujs::JS_STRICT);
+    ///
+    /// state.pushnull();
+    /// state.newuserdatax("Env", HashMap::new(), |js, env: &mut HashMap<String, String>, name| {
+    ///     match env.get(name) {
+    ///         Some(value) => { js.pushstring(value); true },
+    ///         None => false
+    ///     }
+    /// }, |js, env, name| {
+    ///     env.insert(name.to_string(), js.tostring(-1).unwrap());
+    ///     true
+    /// }, |_, env, name| {
+    ///     env.remove(name).is_some()
+    /// }).unwrap();
+    /// state.setglobal("env").unwrap();
+    ///
+    /// state.dostring("env.HOME = '/home/tester';").unwrap();
+    /// state.getglobal("env").unwrap();
+    /// state.getproperty(-1, "HOME").unwrap();
+    /// assert_eq!(state.tostring(-1).unwrap(), "/home/tester");
+    /// ```
+    pub fn newuserdatax<T, H, P, D>(self: &State, tag: &str, value: T,
+                                    has: H, put: P, delete: D) -> Result<(), String>
+        where T: Any + Send,
+              H: FnMut(&State, &mut T, &str) -> bool + Send + 'static,
+              P: FnMut(&State, &mut T, &str) -> bool + Send + 'static,
+              D: FnMut(&State, &mut T, &str) -> bool + Send + 'static
+    {
+        self._newuserdata(Userdata {
+            tag: userdata_tag(tag)?,
+            internal: self.ptr,
+            value: Box::new(value),
+            has: Some(callback(has)),
+            put: Some(callback(put)),
+            delete: Some(callback(delete)),
+            borrowed: false,
+        });
+        Ok(())
+    }
+
+    /// Test if item on stack is a userdata object with tag created by
+    /// State::newuserdata() or State::newuserdatax()
+    pub fn isuserdata(self: &State, idx: i32, tag: &str) -> Result<bool, String> {
+        let tag = userdata_tag(tag)?;
+        Ok(unsafe { ::js_isuserdata((*self.ptr).state, idx, tag.as_ptr()) } != 0)
+    }
+
+    /// Call func with the value owned by the userdata object at
+    /// stack index
+    ///
+    /// Fails if the item is not a userdata object with tag, if the
+    /// value is not of type T or if the value is already borrowed
+    /// by an enclosing call. Userdata objects not created by
+    /// State::newuserdata() or State::newuserdatax() never match.
+    pub fn with_userdata<T, R, F>(self: &State, idx: i32, tag: &str, func: F) -> Result<R, String>
+        where T: Any + Send,
+              F: FnOnce(&mut T) -> R
+    {
+        if !self.isuserdata(idx, tag)? {
+            return Err(format!("Value is not userdata of type '{}'", tag));
+        }
+
+        let tag = userdata_tag(tag)?;
+        let userdata = unsafe {
+            &mut *(::js_touserdata((*self.ptr).state, idx, tag.as_ptr()) as *mut Userdata)
+        };
+        if userdata.borrowed {
+            return Err("Userdata is already borrowed".to_string());
+        }
+
+        userdata.borrowed = true;
+        let _borrow = Borrow(userdata);
+        match userdata.value.downcast_mut::<T>() {
+            Some(value) => Ok(func(value)),
+            None => Err("Userdata value has a different type".to_string())
+        }
+    }
+}
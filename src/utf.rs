@@ -0,0 +1,232 @@
+//! Conversion between Rust strings and the MuJS string representation
+//!
+//! MuJS stores strings as nul terminated modified UTF-8, where the
+//! character U+0000 is encoded as the two byte sequence `0xC0 0x80`
+//! so that it never terminates a string early. Characters which are
+//! created from UTF-16 escapes in script, e.g. `"\uD83D\uDE00"`, are
+//! stored as one three byte sequence per surrogate half.
+//!
+//! When decoding into a Rust string a high surrogate followed by a
+//! low surrogate is combined into the supplementary character they
+//! represent, a lone surrogate can not be represented in a Rust
+//! string and is reported as invalid.
+
+use std::borrow::Cow;
+
+/// Encode a string slice as modified UTF-8
+///
+/// The slice is borrowed as is unless it contains a nul character.
+pub fn encode_str<'a>(value: &'a str) -> Cow<'a, [u8]> {
+    if !value.as_bytes().contains(&0) {
+        return Cow::Borrowed(value.as_bytes());
+    }
+
+    let mut buf = Vec::with_capacity(value.len() + 8);
+    for &b in value.as_bytes() {
+        match b {
+            0 => buf.extend_from_slice(&[0xC0, 0x80]),
+            _ => buf.push(b)
+        }
+    }
+    Cow::Owned(buf)
+}
+
+/// Encode bytes as modified UTF-8 with one character per byte
+///
+/// Each byte is mapped to the character with the same code, in
+/// range U+0000 to U+00FF, which makes `charCodeAt()` in script
+/// return the original byte value.
+pub fn encode_bytes(value: &[u8]) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(value.len() * 2);
+    for &b in value {
+        match b {
+            0 => buf.extend_from_slice(&[0xC0, 0x80]),
+            0x01..=0x7F => buf.push(b),
+            _ => {
+                buf.push(0xC0 | (b >> 6));
+                buf.push(0x80 | (b & 0x3F));
+            }
+        }
+    }
+    buf
+}
+
+/// Decode one character at start of buffer
+///
+/// Returns the code point, including surrogate halves, and the
+/// number of bytes used. Overlong encodings other than the modified
+/// UTF-8 nul and truncated sequences are rejected.
+fn decode_char(buf: &[u8]) -> Option<(u32, usize)> {
+    let b0 = *buf.first()? as u32;
+    let (len, min, init) = match b0 {
+        0x00..=0x7F => return Some((b0, 1)),
+        0xC0..=0xDF => (2, 0x80, b0 & 0x1F),
+        0xE0..=0xEF => (3, 0x800, b0 & 0x0F),
+        0xF0..=0xF4 => (4, 0x10000, b0 & 0x07),
+        _ => return None
+    };
+
+    if buf.len() < len {
+        return None;
+    }
+
+    let mut c = init;
+    for &b in &buf[1..len] {
+        if b & 0xC0 != 0x80 {
+            return None;
+        }
+        c = (c << 6) | (b as u32 & 0x3F);
+    }
+
+    match (c, len) {
+        (0, 2) => Some((0, 2)),
+        (c, _) if c < min || c > 0x10FFFF => None,
+        (c, len) => Some((c, len))
+    }
+}
+
+/// Decode one character at start of buffer, combining a surrogate
+/// pair
+///
+/// Returns the character and the number of bytes used, None for an
+/// invalid sequence or a lone surrogate.
+fn decode_str_char(buf: &[u8]) -> Option<(char, usize)> {
+    let (c, len) = decode_char(buf)?;
+    match c {
+        0xD800..=0xDBFF => match decode_char(&buf[len..]) {
+            Some((low @ 0xDC00..=0xDFFF, low_len)) => {
+                let c = 0x10000 + ((c - 0xD800) << 10) + (low - 0xDC00);
+                ::std::char::from_u32(c).map(|c| (c, len + low_len))
+            },
+            _ => None
+        },
+        0xDC00..=0xDFFF => None,
+        c => ::std::char::from_u32(c).map(|c| (c, len))
+    }
+}
+
+/// Decode modified UTF-8 into a string
+///
+/// On failure the byte offset of the invalid sequence is returned.
+pub fn decode_str(buf: &[u8]) -> Result<String, usize> {
+    if let Ok(s) = ::std::str::from_utf8(buf) {
+        return Ok(s.to_string());
+    }
+
+    let mut s = String::with_capacity(buf.len());
+    let mut pos = 0;
+    while pos < buf.len() {
+        let (c, len) = decode_str_char(&buf[pos..]).ok_or(pos)?;
+        s.push(c);
+        pos += len;
+    }
+    Ok(s)
+}
+
+/// Decode modified UTF-8 into a string replacing invalid sequences
+/// with U+FFFD REPLACEMENT CHARACTER
+pub fn decode_str_lossy(buf: &[u8]) -> String {
+    if let Ok(s) = ::std::str::from_utf8(buf) {
+        return s.to_string();
+    }
+
+    let mut s = String::with_capacity(buf.len());
+    let mut pos = 0;
+    while pos < buf.len() {
+        match decode_str_char(&buf[pos..]) {
+            Some((c, len)) => {
+                s.push(c);
+                pos += len;
+            },
+            None => {
+                s.push('\u{FFFD}');
+                pos += match decode_char(&buf[pos..]) {
+                    Some((_, len)) => len,
+                    None => 1
+                };
+            }
+        }
+    }
+    s
+}
+
+/// Decode modified UTF-8 into bytes, one byte per character
+///
+/// On failure the byte offset of the character which is not in the
+/// range U+0000 to U+00FF is returned.
+pub fn decode_bytes(buf: &[u8]) -> Result<Vec<u8>, usize> {
+    let mut bytes = Vec::with_capacity(buf.len());
+    let mut pos = 0;
+    while pos < buf.len() {
+        match decode_char(&buf[pos..]) {
+            Some((c, len)) if c <= 0xFF => {
+                bytes.push(c as u8);
+                pos += len;
+            },
+            _ => return Err(pos)
+        }
+    }
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use utf;
+
+    #[test]
+    fn encode_str_without_nul_is_borrowed() {
+        match utf::encode_str("Hello") {
+            ::std::borrow::Cow::Borrowed(b) => assert_eq!(b, b"Hello"),
+            _ => panic!("expected borrowed slice")
+        }
+    }
+
+    #[test]
+    fn encode_str_with_nul() {
+        assert_eq!(&*utf::encode_str("a\0b"), &[0x61, 0xC0, 0x80, 0x62]);
+    }
+
+    #[test]
+    fn encode_and_decode_all_bytes() {
+        let bytes: Vec<u8> = (0..256).map(|b| b as u8).collect();
+        let encoded = utf::encode_bytes(&bytes);
+        assert!(!encoded.contains(&0));
+        assert_eq!(utf::decode_bytes(&encoded).unwrap(), bytes);
+    }
+
+    #[test]
+    fn decode_bytes_with_wide_character() {
+        assert_eq!(utf::decode_bytes("ab€".as_bytes()), Err(2));
+    }
+
+    #[test]
+    fn decode_str_with_modified_nul() {
+        assert_eq!(utf::decode_str(&[0x61, 0xC0, 0x80, 0x62]).unwrap(), "a\0b");
+    }
+
+    #[test]
+    fn decode_str_with_surrogate_pair() {
+        let buf = [0xED, 0xA0, 0xBD, 0xED, 0xB8, 0x80];
+        assert_eq!(utf::decode_str(&buf).unwrap(), "\u{1F600}");
+    }
+
+    #[test]
+    fn decode_str_with_lone_surrogate() {
+        let buf = [0x61, 0xED, 0xA0, 0xBD, 0x62];
+        assert_eq!(utf::decode_str(&buf), Err(1));
+        assert_eq!(utf::decode_str_lossy(&buf), "a\u{FFFD}b");
+    }
+
+    #[test]
+    fn decode_str_with_trailing_high_surrogate() {
+        let buf = [0x61, 0xED, 0xA0, 0xBD];
+        assert_eq!(utf::decode_str(&buf), Err(1));
+        assert_eq!(utf::decode_str_lossy(&buf), "a\u{FFFD}");
+    }
+
+    #[test]
+    fn decode_str_with_invalid_byte() {
+        assert_eq!(utf::decode_str(&[0x61, 0xFF]), Err(1));
+        assert_eq!(utf::decode_str_lossy(&[0x61, 0xFF, 0x62]), "a\u{FFFD}b");
+    }
+}
@@ -0,0 +1,203 @@
+//! Conversion between Rust values and Javascript values on the stack
+
+use State;
+
+/// Types which can be pushed onto the stack as a Javascript value
+///
+/// # Examples
+///
+/// ```
+/// use mujs;
+/// use mujs::ToJs;
+///
+/// let state = mujs::State::new(mujs::JS_STRICT);
+///
+/// vec![1, 2, 3].push_js(&state);
+/// assert!(state.isarray(0));
+///
+/// Some("Hello").push_js(&state);
+/// assert_eq!(state.tostring(1).unwrap(), "Hello");
+/// ```
+pub trait ToJs {
+    /// Push value on top of stack
+    fn push_js(&self, state: &State);
+}
+
+impl<'a, T> ToJs for &'a T where T: ToJs + ?Sized {
+    fn push_js(self: &&'a T, state: &State) {
+        (**self).push_js(state)
+    }
+}
+
+impl ToJs for () {
+    fn push_js(self: &(), state: &State) {
+        state.pushundefined();
+    }
+}
+
+impl ToJs for bool {
+    fn push_js(self: &bool, state: &State) {
+        state.pushboolean(*self);
+    }
+}
+
+macro_rules! number_to_js {
+    ($($t:ty)*) => ($(
+        impl ToJs for $t {
+            fn push_js(self: &$t, state: &State) {
+                state.pushnumber(*self as f64);
+            }
+        }
+    )*)
+}
+
+number_to_js! { i8 i16 i32 u8 u16 u32 f32 f64 }
+
+impl ToJs for str {
+    fn push_js(self: &str, state: &State) {
+        state.pushstring(self);
+    }
+}
+
+impl ToJs for String {
+    fn push_js(self: &String, state: &State) {
+        state.pushstring(self);
+    }
+}
+
+impl<T> ToJs for Option<T> where T: ToJs {
+    fn push_js(self: &Option<T>, state: &State) {
+        match *self {
+            Some(ref value) => value.push_js(state),
+            None => state.pushnull()
+        }
+    }
+}
+
+impl<T> ToJs for [T] where T: ToJs {
+    fn push_js(self: &[T], state: &State) {
+        state.newarray();
+        for (i, value) in self.iter().enumerate() {
+            value.push_js(state);
+            state.setindex(-2, i as i32);
+        }
+    }
+}
+
+impl<T> ToJs for Vec<T> where T: ToJs {
+    fn push_js(self: &Vec<T>, state: &State) {
+        self.as_slice().push_js(state)
+    }
+}
+
+/// Types which can be converted from a Javascript value on the stack
+///
+/// Conversions are strict, a value of another type is an error
+/// describing the expected and the actual type rather than being
+/// coerced.
+///
+/// # Examples
+///
+/// ```
+/// use mujs;
+/// use mujs::FromJs;
+///
+/// let state = mujs::State::new(mujs::JS_STRICT);
+///
+/// state.dostring("var list = [1, 2, 3];").unwrap();
+/// state.getglobal("list").unwrap();
+/// assert_eq!(Vec::<u8>::from_js(&state, 0).unwrap(), vec![1, 2, 3]);
+/// assert_eq!(String::from_js(&state, 0).unwrap_err(), "expected a string, got object");
+/// ```
+pub trait FromJs: Sized {
+    /// Convert value at stack index
+    fn from_js(state: &State, idx: i32) -> Result<Self, String>;
+}
+
+fn expected(state: &State, idx: i32, what: &str) -> String {
+    format!("expected {}, got {}", what, state.type_of(idx))
+}
+
+impl FromJs for bool {
+    fn from_js(state: &State, idx: i32) -> Result<bool, String> {
+        match state.isboolean(idx) {
+            true => state.toboolean(idx),
+            false => Err(expected(state, idx, "a boolean"))
+        }
+    }
+}
+
+impl FromJs for f64 {
+    fn from_js(state: &State, idx: i32) -> Result<f64, String> {
+        match state.isnumber(idx) {
+            true => state.tonumber(idx),
+            false => Err(expected(state, idx, "a number"))
+        }
+    }
+}
+
+impl FromJs for f32 {
+    fn from_js(state: &State, idx: i32) -> Result<f32, String> {
+        f64::from_js(state, idx).map(|n| n as f32)
+    }
+}
+
+macro_rules! integer_from_js {
+    ($($t:ident)*) => ($(
+        impl FromJs for $t {
+            fn from_js(state: &State, idx: i32) -> Result<$t, String> {
+                let n = f64::from_js(state, idx)?;
+                // MAX is one less than a power of two, which as f64
+                // may round up to that power, MAX + 1 is exact either
+                // way and serves as exclusive upper bound.
+                let end = $t::MAX as f64 + 1.0;
+                match n.fract() == 0.0 && n >= $t::MIN as f64 && n < end {
+                    true => Ok(n as $t),
+                    false => Err(format!("expected an integer in range of {}, got {}",
+                                         stringify!($t), n))
+                }
+            }
+        }
+    )*)
+}
+
+integer_from_js! { i8 i16 i32 i64 u8 u16 u32 u64 usize }
+
+impl FromJs for String {
+    fn from_js(state: &State, idx: i32) -> Result<String, String> {
+        match state.isstring(idx) {
+            true => state.to_str(idx),
+            false => Err(expected(state, idx, "a string"))
+        }
+    }
+}
+
+/// Undefined and null convert to None
+impl<T> FromJs for Option<T> where T: FromJs {
+    fn from_js(state: &State, idx: i32) -> Result<Option<T>, String> {
+        match state.isundefined(idx) || state.isnull(idx) {
+            true => Ok(None),
+            false => T::from_js(state, idx).map(Some)
+        }
+    }
+}
+
+impl<T> FromJs for Vec<T> where T: FromJs {
+    fn from_js(state: &State, idx: i32) -> Result<Vec<T>, String> {
+        if !state.isarray(idx) {
+            return Err(expected(state, idx, "an array"));
+        }
+        let idx = match idx < 0 {
+            true => state.gettop() + idx,
+            false => idx
+        };
+        let mut items = Vec::new();
+        for i in 0..state.getlength(idx) {
+            state.getindex(idx, i);
+            let item = T::from_js(state, -1);
+            state.pop(1);
+            items.push(item.map_err(|err| format!("element {}: {}", i, err))?);
+        }
+        Ok(items)
+    }
+}
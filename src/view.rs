@@ -0,0 +1,187 @@
+//! Read-only views of Rust collections
+
+use std::collections::{BTreeMap, HashMap};
+use std::hash::BuildHasher;
+use std::sync::Arc;
+
+use {State, ToJs, DynamicObject};
+
+/// Collection exposed to scripts without copying its elements
+///
+/// Array-like views report a length and are keyed by index, their
+/// prototype is Array.prototype so that non-mutating array methods
+/// such as forEach() and slice() work. Object-like views are keyed
+/// by name. Elements are converted when accessed.
+///
+/// Implemented for vectors, slices and maps with string keys of
+/// values implementing ToJs.
+pub trait JsView: Send + Sync + 'static {
+    /// Number of elements of an array-like view, None for an
+    /// object-like view
+    fn length(&self) -> Option<usize>;
+
+    /// Test if key is present
+    fn has(&self, key: &str) -> bool;
+
+    /// Push the value of key, only called when has() returned true
+    fn get(&self, state: &State, key: &str);
+}
+
+/// Parse an array index, rejecting forms such as `01` or `+1` which
+/// name other properties
+fn parse_index(key: &str) -> Option<usize> {
+    match key == "0" || !key.starts_with('0') {
+        true if key.chars().all(|c| c.is_ascii_digit()) => key.parse().ok(),
+        _ => None
+    }
+}
+
+impl<T> JsView for [T] where T: ToJs + Send + Sync + 'static {
+    fn length(self: &[T]) -> Option<usize> {
+        Some(self.len())
+    }
+
+    fn has(self: &[T], key: &str) -> bool {
+        matches!(parse_index(key), Some(i) if i < self.len())
+    }
+
+    fn get(self: &[T], state: &State, key: &str) {
+        if let Some(value) = parse_index(key).and_then(|i| <[T]>::get(self, i)) {
+            value.push_js(state);
+        }
+    }
+}
+
+impl<T> JsView for Vec<T> where T: ToJs + Send + Sync + 'static {
+    fn length(self: &Vec<T>) -> Option<usize> {
+        Some(self.len())
+    }
+
+    fn has(self: &Vec<T>, key: &str) -> bool {
+        self.as_slice().has(key)
+    }
+
+    fn get(self: &Vec<T>, state: &State, key: &str) {
+        JsView::get(self.as_slice(), state, key)
+    }
+}
+
+impl<T, S> JsView for HashMap<String, T, S>
+    where T: ToJs + Send + Sync + 'static,
+          S: BuildHasher + Send + Sync + 'static
+{
+    fn length(self: &HashMap<String, T, S>) -> Option<usize> {
+        None
+    }
+
+    fn has(self: &HashMap<String, T, S>, key: &str) -> bool {
+        self.contains_key(key)
+    }
+
+    fn get(self: &HashMap<String, T, S>, state: &State, key: &str) {
+        if let Some(value) = HashMap::get(self, key) {
+            value.push_js(state);
+        }
+    }
+}
+
+impl<T> JsView for BTreeMap<String, T> where T: ToJs + Send + Sync + 'static {
+    fn length(self: &BTreeMap<String, T>) -> Option<usize> {
+        None
+    }
+
+    fn has(self: &BTreeMap<String, T>, key: &str) -> bool {
+        self.contains_key(key)
+    }
+
+    fn get(self: &BTreeMap<String, T>, state: &State, key: &str) {
+        if let Some(value) = BTreeMap::get(self, key) {
+            value.push_js(state);
+        }
+    }
+}
+
+/// DynamicObject sharing a view, rejecting writes and deletes
+struct ViewObject<V: ?Sized> {
+    view: Arc<V>,
+}
+
+impl<V> ViewObject<V> where V: JsView + ?Sized {
+
+    /// Ignore a write or delete, which throws in strict mode
+    fn read_only(self: &ViewObject<V>, state: &State, name: &str) -> bool {
+        if state._is_strict() {
+            state._hook_typeerror(format!("'{}' is read-only", name));
+        }
+        true
+    }
+}
+
+impl<V> DynamicObject for ViewObject<V> where V: JsView + ?Sized {
+    fn has(self: &ViewObject<V>, name: &str) -> bool {
+        (name == "length" && self.view.length().is_some()) || self.view.has(name)
+    }
+
+    fn get(self: &mut ViewObject<V>, state: &State, name: &str) {
+        match self.view.length() {
+            Some(length) if name == "length" => state.pushnumber(length as f64),
+            _ => self.view.get(state, name)
+        }
+    }
+
+    fn set(self: &mut ViewObject<V>, state: &State, name: &str) -> bool {
+        self.read_only(state, name)
+    }
+
+    fn delete(self: &mut ViewObject<V>, state: &State, name: &str) -> bool {
+        self.read_only(state, name)
+    }
+}
+
+impl State {
+
+    /// Push a read-only object sharing view
+    ///
+    /// Elements are converted on access rather than copied into the
+    /// Javascript heap, the view is kept alive until the object is
+    /// garbage collected. Assignments and deletes are ignored, or
+    /// throw a TypeError in a State created with JS_STRICT. Code
+    /// opting into strict mode with a `"use strict"` directive is not
+    /// detected.
+    ///
+    /// Key enumeration is not supported, `for in` loops and
+    /// Object.keys() see no properties, iterate array-like views by
+    /// index instead.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use mujs;
+    /// use std::sync::Arc;
+    ///
+    /// let samples = Arc::new(vec![1.5, 2.5, 3.0]);
+    ///
+    /// let state = mujs::State::new(mujs::JS_STRICT);
+    /// state.push_view(&samples).unwrap();
+    /// state.setglobal("samples").unwrap();
+    ///
+    /// state.dostring("var total = 0;
+    ///                 for (var i = 0; i < samples.length; ++i)
+    ///                     total += samples[i];").unwrap();
+    /// state.getglobal("total").unwrap();
+    /// assert_eq!(state.tonumber(-1).unwrap(), 7.0);
+    /// ```
+    pub fn push_view<V>(self: &State, view: &Arc<V>) -> Result<(), String>
+        where V: JsView + ?Sized
+    {
+        match view.length() {
+            Some(_) => {
+                self.getglobal("Array")?;
+                self.getproperty(-1, "prototype")?;
+                self.remove(-2);
+            },
+            None => self.pushnull()
+        }
+        self._newdynamic(ViewObject { view: view.clone() })
+    }
+}
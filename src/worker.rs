@@ -0,0 +1,128 @@
+//! Running a State on a dedicated worker thread
+
+use std::sync::mpsc::{channel, Sender, Receiver, TryRecvError};
+use std::thread::{self, JoinHandle};
+use std::panic::{self, AssertUnwindSafe};
+
+use {State, StateFlags};
+
+type Job = Box<dyn FnOnce(&State) + Send>;
+
+/// Owns a State on a dedicated thread
+///
+/// Jobs are closures which are sent to the worker thread through a
+/// channel and executed in order with exclusive access to the
+/// State. The result of each job is returned through a JobHandle.
+///
+/// The worker thread is stopped and the State is freed when the
+/// JsWorker is dropped, after all queued jobs have been executed.
+///
+/// # Examples
+///
+/// ```
+/// use mujs;
+///
+/// let worker = mujs::JsWorker::new(mujs::JS_STRICT);
+///
+/// worker.execute(|state| state.dostring("var x = 20;").unwrap()).unwrap();
+///
+/// let x = worker.execute(|state| {
+///     state.getglobal("x").unwrap();
+///     state.tonumber(-1).unwrap()
+/// }).unwrap();
+///
+/// assert_eq!(x.wait().unwrap(), 20.0);
+/// ```
+pub struct JsWorker {
+    sender: Option<Sender<Job>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+/// Pending result of a job executed by a JsWorker
+pub struct JobHandle<R> {
+    receiver: Receiver<R>,
+}
+
+impl<R> JobHandle<R> {
+
+    /// Block until the job has been executed and return its result
+    ///
+    /// Returns an error if the job panicked or the worker stopped
+    /// before executing it.
+    pub fn wait(self: JobHandle<R>) -> Result<R, String> {
+        self.receiver.recv().map_err(|_| "Job failed to complete".to_string())
+    }
+
+    /// Return the result of the job if it has been executed
+    pub fn try_wait(self: &JobHandle<R>) -> Result<Option<R>, String> {
+        match self.receiver.try_recv() {
+            Ok(value) => Ok(Some(value)),
+            Err(TryRecvError::Empty) => Ok(None),
+            Err(TryRecvError::Disconnected) => Err("Job failed to complete".to_string())
+        }
+    }
+}
+
+impl JsWorker {
+
+    /// Constructs a new State with flags on a new worker thread
+    pub fn new(flags: StateFlags) -> JsWorker {
+        JsWorker::spawn(move || State::new(flags))
+    }
+
+    /// Move an existing State to a new worker thread
+    pub fn with_state(state: State) -> JsWorker {
+        JsWorker::spawn(move || state)
+    }
+
+    fn spawn<F>(init: F) -> JsWorker
+        where F: FnOnce() -> State + Send + 'static
+    {
+        let (sender, receiver) = channel::<Job>();
+        let thread = thread::spawn(move || {
+            let state = init();
+            for job in receiver {
+                // A panicking job drops its result sender, which is
+                // reported through the JobHandle, and the worker
+                // continues with the next job.
+                let _ = panic::catch_unwind(AssertUnwindSafe(|| job(&state)));
+            }
+        });
+
+        JsWorker {
+            sender: Some(sender),
+            thread: Some(thread),
+        }
+    }
+
+    /// Queue a closure for execution on the worker thread
+    ///
+    /// Returns an error if the worker thread has stopped.
+    pub fn execute<F, R>(self: &JsWorker, func: F) -> Result<JobHandle<R>, String>
+        where F: FnOnce(&State) -> R + Send + 'static,
+              R: Send + 'static
+    {
+        let (sender, receiver) = channel();
+        let job: Job = Box::new(move |state: &State| {
+            let _ = sender.send(func(state));
+        });
+
+        match self.sender {
+            Some(ref jobs) => jobs.send(job).map_err(|_| "Worker has stopped".to_string())?,
+            None => return Err("Worker has stopped".to_string())
+        }
+
+        Ok(JobHandle {
+            receiver,
+        })
+    }
+}
+
+impl Drop for JsWorker {
+    fn drop(self: &mut JsWorker) {
+        self.sender.take();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}